@@ -0,0 +1,190 @@
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Signatures of the tables the kernel knows about
+pub const MADT: [u8; 4] = *b"APIC";
+pub const FADT: [u8; 4] = *b"FACP";
+pub const HPET: [u8; 4] = *b"HPET";
+
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+// the BIOS data area holds the real mode segment of the EBDA at this address
+const EBDA_SEGMENT_POINTER: u64 = 0x40E;
+// only the first KiB of the EBDA is searched
+const EBDA_SEARCH_LENGTH: u64 = 1024;
+// the main BIOS area below 1 MiB
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+// the RSDP is always on a 16 byte boundary
+const RSDP_ALIGN: u64 = 16;
+// bytes covered by the checksum of the ACPI 1.0 part of the RSDP
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Root System Description Pointer, the fields after `rsdt_address` only exist from revision 2
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header every system description table starts with
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP with a valid checksum in the EBDA or the BIOS area
+    RsdpNotFound,
+    /// The RSDT or XSDT the RSDP points to has a wrong checksum
+    InvalidRootTable,
+}
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+// physical address of the RSDT or XSDT, 0 before `init`
+static ROOT_TABLE: AtomicU64 = AtomicU64::new(0);
+// the XSDT has 64 bit entries, the RSDT 32 bit ones
+static ROOT_IS_XSDT: AtomicBool = AtomicBool::new(false);
+
+/// Locate the RSDP and the root table so `find_table` can look up tables.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> Result<(), AcpiError> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
+    let rsdp_address = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+    let rsdp: Rsdp = read_phys(rsdp_address);
+
+    // revision 0 is ACPI 1.0, which only has the RSDT
+    let (root, is_xsdt) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (PhysAddr::new(rsdp.xsdt_address), true)
+    } else {
+        (PhysAddr::new(u64::from(rsdp.rsdt_address)), false)
+    };
+    if !table_is_valid(root) {
+        return Err(AcpiError::InvalidRootTable);
+    }
+    ROOT_IS_XSDT.store(is_xsdt, Ordering::SeqCst);
+    ROOT_TABLE.store(root.as_u64(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Physical address of the RSDP, searched in the EBDA and then in the BIOS area.
+///
+/// Returns `None` before `init` set the physical memory offset, the memory can not be
+/// read without it.
+pub fn find_rsdp() -> Option<PhysAddr> {
+    if PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) == 0 {
+        return None;
+    }
+    let ebda_segment: u16 = unsafe { read_phys(PhysAddr::new(EBDA_SEGMENT_POINTER)) };
+    let ebda_start = u64::from(ebda_segment) << 4;
+    let in_ebda = if ebda_start != 0 {
+        search_rsdp(ebda_start, ebda_start + EBDA_SEARCH_LENGTH)
+    } else {
+        None
+    };
+    in_ebda.or_else(|| search_rsdp(BIOS_AREA_START, BIOS_AREA_END))
+}
+
+fn search_rsdp(start: u64, end: u64) -> Option<PhysAddr> {
+    (start..end)
+        .step_by(RSDP_ALIGN as usize)
+        .map(PhysAddr::new)
+        .find(|&address| unsafe { rsdp_is_valid(address) })
+}
+
+unsafe fn rsdp_is_valid(address: PhysAddr) -> bool {
+    let signature: [u8; 8] = read_phys(address);
+    if signature != RSDP_SIGNATURE || !checksum_is_zero(address, RSDP_V1_LENGTH) {
+        return false;
+    }
+    let rsdp: Rsdp = read_phys(address);
+    // from revision 2 the extended checksum covers the whole structure
+    rsdp.revision < 2 || checksum_is_zero(address, rsdp.length as usize)
+}
+
+/// Physical address of the first table with the given signature and a valid checksum.
+///
+/// Returns `None` before `init` succeeded.
+pub fn find_table(signature: [u8; 4]) -> Option<PhysAddr> {
+    let root = ROOT_TABLE.load(Ordering::SeqCst);
+    if root == 0 {
+        return None;
+    }
+    let root = PhysAddr::new(root);
+    let is_xsdt = ROOT_IS_XSDT.load(Ordering::SeqCst);
+    let entry_size = if is_xsdt { 8 } else { 4 };
+    let header: SdtHeader = unsafe { read_phys(root) };
+    let entries = (header.length as usize - mem::size_of::<SdtHeader>()) / entry_size;
+
+    (0..entries)
+        .map(|index| {
+            let entry = root + mem::size_of::<SdtHeader>() + index * entry_size;
+            let address = unsafe {
+                if is_xsdt {
+                    read_phys::<u64>(entry)
+                } else {
+                    u64::from(read_phys::<u32>(entry))
+                }
+            };
+            PhysAddr::new(address)
+        })
+        .find(|&table| {
+            let header: SdtHeader = unsafe { read_phys(table) };
+            header.signature == signature && table_is_valid(table)
+        })
+}
+
+/// Read the header of the table at `address`.
+///
+/// This function is unsafe because the caller must guarantee that `address`
+/// points to a system description table, like one returned by `find_table`.
+pub unsafe fn table_header(address: PhysAddr) -> SdtHeader {
+    read_phys(address)
+}
+
+fn table_is_valid(address: PhysAddr) -> bool {
+    let header: SdtHeader = unsafe { read_phys(address) };
+    let length = header.length as usize;
+    length >= mem::size_of::<SdtHeader>() && unsafe { checksum_is_zero(address, length) }
+}
+
+// all bytes of a valid structure add up to 0 modulo 256
+unsafe fn checksum_is_zero(address: PhysAddr, length: usize) -> bool {
+    let start = phys_to_virt(address).as_ptr::<u8>();
+    (0..length).fold(0u8, |sum, offset| {
+        sum.wrapping_add(start.add(offset).read_volatile())
+    }) == 0
+}
+
+fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) + address.as_u64())
+}
+
+/// Read a `T` at the physical `address`, e.g. an entry of a table.
+///
+/// ACPI structures are not aligned, so `T` does not need to be. This function is
+/// unsafe because the caller must guarantee that `address` points to a `T`.
+pub(crate) unsafe fn read_phys<T: Copy>(address: PhysAddr) -> T {
+    phys_to_virt(address).as_ptr::<T>().read_unaligned()
+}
@@ -1,24 +1,108 @@
+pub mod buddy;
 pub mod bump;
+pub mod early;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod slab;
 
-use crate::allocator::bump::{BumpAllocator, Locked};
-use crate::allocator::fixed_size_block::FixedSizeBlockAllocator;
-use crate::allocator::linked_list::LinkedListAllocator;
+use crate::allocator::bump::Locked;
+use crate::allocator::early::EarlyAllocator;
+use crate::{memory, serial_println};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
+};
 use x86_64::VirtAddr;
 
 pub struct Dummy;
 
+// the global allocator is selected by the `alloc-*` features, if more than one is enabled
+// the first of bump, linked list, buddy and fixed size block wins
+#[cfg(feature = "alloc-bump")]
+type HeapAllocator = bump::BumpAllocator;
+#[cfg(all(feature = "alloc-linked", not(feature = "alloc-bump")))]
+type HeapAllocator = linked_list::LinkedListAllocator;
+#[cfg(all(
+    feature = "alloc-buddy",
+    not(any(feature = "alloc-bump", feature = "alloc-linked"))
+))]
+type HeapAllocator = buddy::BuddyAllocator;
+#[cfg(all(
+    feature = "alloc-fixed",
+    not(any(
+        feature = "alloc-bump",
+        feature = "alloc-linked",
+        feature = "alloc-buddy"
+    ))
+))]
+type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked",
+    feature = "alloc-buddy",
+    feature = "alloc-fixed"
+)))]
+compile_error!("enable one of the features alloc-bump, alloc-linked, alloc-buddy or alloc-fixed");
+
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+/// Size of the early boot heap in the kernel image, the total of all allocations
+/// made before `init_heap` must fit into it
+pub const EARLY_HEAP_SIZE: usize = 16 * 1024;
+
+static EARLY_ALLOCATOR: EarlyAllocator<EARLY_HEAP_SIZE> = EarlyAllocator::new();
+
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static GLOBAL_ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// Serves allocations from `EARLY_ALLOCATOR` until `init_heap` is done and from
+/// `ALLOCATOR` after that.
+///
+/// Early allocations stay valid after the switch, freeing them does nothing.
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if heap_initialized() {
+            ALLOCATOR.alloc(layout)
+        } else {
+            EARLY_ALLOCATOR.alloc(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if EARLY_ALLOCATOR.contains(ptr) {
+            EARLY_ALLOCATOR.dealloc(ptr, layout);
+        } else {
+            ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if EARLY_ALLOCATOR.contains(ptr) {
+            // moves the data to the real heap once it exists
+            realloc_by_copy(self, ptr, layout, new_size)
+        } else {
+            ALLOCATOR.realloc(ptr, layout, new_size)
+        }
+    }
+}
+
+/// Whether `ptr` was allocated before the heap was initialized
+pub fn is_early_allocation(ptr: *const u8) -> bool {
+    EARLY_ALLOCATOR.contains(ptr)
+}
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
+static HEAP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+// end of the mapped heap, moves up with `grow_heap`
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START + HEAP_SIZE);
+
 unsafe impl GlobalAlloc for Dummy {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         null_mut()
@@ -30,44 +114,527 @@ unsafe impl GlobalAlloc for Dummy {
 }
 
 pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    mapper: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
 ) -> Result<(), MapToError<Size4KiB>> {
-    // Creating the page range
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
+    map_heap_range(HEAP_START, HEAP_START + HEAP_SIZE, mapper, frame_allocator)?;
 
-    // Mapping the pages
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
-    }
+    // the page right below and right above the heap stay unmapped as guard pages,
+    // so running off either end faults instead of corrupting whatever is next to it
+    let guard_below = Page::containing_address(VirtAddr::new((HEAP_START - 1) as u64));
+    check_guard_page(guard_below, mapper)?;
+    check_guard_page(guard_page_above(HEAP_START + HEAP_SIZE), mapper)?;
 
     // Init the allocator
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
     }
+    // so the statistics have the free bytes before the first allocation
+    ALLOCATOR.counters.set_capacity(HEAP_SIZE);
+    HEAP_INITIALIZED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Map `additional_pages` more pages directly after the end of the heap and hand
+/// them to the allocator.
+///
+/// The guard page above the heap moves up with the new end. Must be called after
+/// `init_heap`.
+pub fn grow_heap(
+    additional_pages: usize,
+    mapper: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(heap_initialized(), "grow_heap called before init_heap");
+    let heap_end = HEAP_END.load(Ordering::SeqCst);
+    let size = additional_pages * Size4KiB::SIZE as usize;
+    let new_end = heap_end + size;
 
+    check_guard_page(guard_page_above(new_end), mapper)?;
+    map_heap_range(heap_end, new_end, mapper, frame_allocator)?;
+
+    unsafe { ALLOCATOR.lock().extend(size) };
+    HEAP_END.store(new_end, Ordering::SeqCst);
+    ALLOCATOR.counters.set_capacity(new_end - HEAP_START);
     Ok(())
 }
 
+/// Current size of the heap in bytes, `HEAP_SIZE` plus everything added by `grow_heap`
+pub fn heap_size() -> usize {
+    HEAP_END.load(Ordering::SeqCst) - HEAP_START
+}
+
+/// Map the heap memory between `start` and `end`, which must be page aligned.
+///
+/// Every 2 MiB aligned part of the range is mapped with a huge page if the frame
+/// allocator has a 2 MiB frame left, everything else with 4 KiB pages.
+fn map_heap_range(
+    start: usize,
+    end: usize,
+    mapper: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
+) -> Result<(), MapToError<Size4KiB>> {
+    const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+    const HUGE_PAGE_SIZE: usize = Size2MiB::SIZE as usize;
+
+    // the heap only holds data so it is never executable
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if memory::no_execute_enabled() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    // also counts the frames the mapper takes for new page tables
+    let mut frame_allocator = CountingFrameAllocator {
+        inner: frame_allocator,
+        frames: 0,
+    };
+    let (mut pages, mut huge_pages) = (0, 0);
+
+    let mut addr = start;
+    while addr < end {
+        if addr % HUGE_PAGE_SIZE == 0
+            && end - addr >= HUGE_PAGE_SIZE
+            && map_huge_heap(addr, flags, mapper, &mut frame_allocator)?
+        {
+            huge_pages += 1;
+            addr += HUGE_PAGE_SIZE;
+            continue;
+        }
+
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(addr as u64));
+        let frame = match FrameAllocator::<Size4KiB>::allocate_frame(&mut frame_allocator) {
+            Some(frame) => frame,
+            None => {
+                log::error!(
+                    "heap: out of frames after {} frames were allocated for the heap",
+                    frame_allocator.frames
+                );
+                return Err(MapToError::FrameAllocationFailed);
+            }
+        };
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, &mut frame_allocator)?
+                .flush()
+        };
+        pages += 1;
+        addr += PAGE_SIZE;
+    }
+    log::info!(
+        "heap: mapped {} pages and {} huge pages using {} frames",
+        pages,
+        huge_pages,
+        frame_allocator.frames
+    );
+    Ok(())
+}
+
+/// Map the 2 MiB of heap at `addr` with a single huge page.
+///
+/// Returns false if there is no 2 MiB frame left, then the caller falls back to 4 KiB pages.
+fn map_huge_heap(
+    addr: usize,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size2MiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
+) -> Result<bool, MapToError<Size4KiB>> {
+    let page: Page<Size2MiB> = Page::containing_address(VirtAddr::new(addr as u64));
+    let frame = match FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator) {
+        Some(frame) => frame,
+        None => return Ok(false),
+    };
+    // `map_to` sets HUGE_PAGE itself, it is only spelled out for the reader
+    let flags = flags | PageTableFlags::HUGE_PAGE;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(true)
+        }
+        Err(MapToError::FrameAllocationFailed) => Err(MapToError::FrameAllocationFailed),
+        Err(MapToError::ParentEntryHugePage) => Err(MapToError::ParentEntryHugePage),
+        Err(MapToError::PageAlreadyMapped(frame)) => Err(MapToError::PageAlreadyMapped(
+            PhysFrame::containing_address(frame.start_address()),
+        )),
+    }
+}
+
+/// Passes allocations through to `inner` and counts the frames it hands out
+struct CountingFrameAllocator<'a, A> {
+    inner: &'a mut A,
+    frames: usize,
+}
+
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB>
+    for CountingFrameAllocator<'_, A>
+{
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.inner.allocate_frame();
+        if frame.is_some() {
+            self.frames += 1;
+        }
+        frame
+    }
+}
+
+unsafe impl<A: FrameAllocator<Size2MiB>> FrameAllocator<Size2MiB>
+    for CountingFrameAllocator<'_, A>
+{
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.inner.allocate_frame();
+        if frame.is_some() {
+            // counted in 4 KiB frames like the rest
+            self.frames += (Size2MiB::SIZE / Size4KiB::SIZE) as usize;
+        }
+        frame
+    }
+}
+
+fn guard_page_above(heap_end: usize) -> Page {
+    Page::containing_address(VirtAddr::new(heap_end as u64))
+}
+
+fn check_guard_page(
+    guard: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    match mapper.translate_page(guard) {
+        Ok(frame) => Err(MapToError::PageAlreadyMapped(frame)),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Print the layout of a failed allocation and the allocator statistics to serial.
+///
+/// Meant to be called from an `#[alloc_error_handler]`.
+pub fn report_alloc_error(layout: Layout) {
+    serial_println!(
+        "ALLOCATION ERROR: size {} align {}",
+        layout.size(),
+        layout.align()
+    );
+    // `try_lock` because the error handler must not deadlock on a lock held by the failed allocation
+    match ALLOCATOR.try_lock() {
+        Some(allocator) => report_stats(&allocator),
+        None => {
+            serial_println!("allocator is locked, no statistics available");
+        }
+    }
+}
+
+#[cfg(feature = "alloc-bump")]
+fn report_stats(allocator: &HeapAllocator) {
+    serial_println!(
+        "bump allocator: {} used, {} free",
+        crate::util::format_bytes(allocator.used() as u64),
+        crate::util::format_bytes(allocator.free() as u64)
+    );
+}
+
+#[cfg(all(
+    not(feature = "alloc-bump"),
+    any(feature = "alloc-linked", feature = "alloc-buddy")
+))]
+fn report_stats(_allocator: &HeapAllocator) {
+    serial_println!("no statistics available for this allocator");
+}
+
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked",
+    feature = "alloc-buddy"
+)))]
+fn report_stats(allocator: &HeapAllocator) {
+    allocator.report();
+}
+
+/// Whether `init_heap` has completed, i.e. allocations can succeed
+pub fn heap_initialized() -> bool {
+    HEAP_INITIALIZED.load(Ordering::SeqCst)
+}
+
+/// Allocate `size` bytes aligned to `align` straight from the global allocator.
+///
+/// Returns null if the allocation fails or `align` is not a power of two. Lets
+/// tests ask for layouts that no `Box` would produce.
+pub fn alloc_raw(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        // zero sized allocations are not allowed by `GlobalAlloc`
+        Ok(layout) if layout.size() > 0 => unsafe { GLOBAL_ALLOCATOR.alloc(layout) },
+        _ => null_mut(),
+    }
+}
+
+/// Free a block returned by `alloc_raw`.
+///
+/// ## Safety
+///
+/// `ptr` must come from `alloc_raw` with the same `size` and `align`.
+pub unsafe fn dealloc_raw(ptr: *mut u8, size: usize, align: usize) {
+    GLOBAL_ALLOCATOR.dealloc(ptr, Layout::from_size_align_unchecked(size, align));
+}
+
+/// Reallocate by allocating a new block, copying the data and freeing the old block.
+///
+/// This is what the default `GlobalAlloc::realloc` does, the allocators use it when
+/// the allocation can not be resized in place.
+unsafe fn realloc_by_copy<A: GlobalAlloc>(
+    allocator: &A,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new_ptr = allocator.alloc(new_layout);
+    if !new_ptr.is_null() {
+        core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+        allocator.dealloc(ptr, layout);
+    }
+    new_ptr
+}
+
+/// Allocation statistics that can be read without taking the allocator lock, so an
+/// interrupt handler can read them while the interrupted code holds the lock.
+///
+/// Allocators apply the changes of an operation right after releasing their lock.
+/// A snapshot taken in between may miss the operations that are still in flight
+/// and mix counters from before and after one, once they completed the counters
+/// match the allocator again.
+pub struct AllocCounters {
+    used: AtomicUsize,
+    capacity: AtomicUsize,
+    peak_used: AtomicUsize,
+    allocations: AtomicUsize,
+}
+
+impl AllocCounters {
+    pub const fn new() -> Self {
+        AllocCounters {
+            used: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+            peak_used: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Apply one operation that changed the live allocations by `allocations` and
+    /// the used bytes from `used_before` to `used_after`
+    pub fn record(
+        &self,
+        allocations: isize,
+        used_before: usize,
+        used_after: usize,
+        capacity: usize,
+    ) {
+        // changes rather than values, so operations finishing out of order add up correctly
+        if allocations >= 0 {
+            self.allocations
+                .fetch_add(allocations as usize, Ordering::Relaxed);
+        } else {
+            self.allocations
+                .fetch_sub(allocations.unsigned_abs(), Ordering::Relaxed);
+        }
+        let used = if used_after >= used_before {
+            let grown = used_after - used_before;
+            self.used.fetch_add(grown, Ordering::Relaxed) + grown
+        } else {
+            let shrunk = used_before - used_after;
+            self.used.fetch_sub(shrunk, Ordering::Relaxed) - shrunk
+        };
+        self.peak_used.fetch_max(used, Ordering::Relaxed);
+        self.set_capacity(capacity);
+    }
+
+    /// Set the size of the heap, when it is created or grows without an allocation
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// The current statistics
+    pub fn snapshot(&self) -> AllocStats {
+        let used = self.used.load(Ordering::Relaxed);
+        AllocStats {
+            used,
+            free: self.capacity.load(Ordering::Relaxed).saturating_sub(used),
+            peak_used: self.peak_used.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for AllocCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A snapshot of the heap usage of an allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Bytes currently in use
+    pub used: usize,
+    /// Bytes still available
+    pub free: usize,
+    /// The maximum of `used` since the heap was initialized
+    pub peak_used: usize,
+    /// Number of live allocations
+    pub allocations: usize,
+}
+
 /// Align the given address `addr` upwards to alignment `align`.
 ///
-/// Requires that `align` is a power of two
-fn align_up(addr: usize, align: usize) -> usize {
+/// Requires that `align` is a power of two. Wraps around for addresses within
+/// `align` of `usize::MAX`, use `align_up_checked` where that can happen.
+pub fn align_up(addr: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two(), "alignment must be a power of two");
     // let remainder = addr % align;
     // if remainder == 0 {
     //     addr // already aligned
     // } else {
     //     addr - remainder + align
     // }
-    (addr + align - 1) & !(align - 1)
+    addr.wrapping_add(align - 1) & !(align - 1)
+}
+
+/// Like `align_up`, but returns `None` if the aligned address does not fit in an `usize`
+pub fn align_up_checked(addr: usize, align: usize) -> Option<usize> {
+    debug_assert!(align.is_power_of_two(), "alignment must be a power of two");
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+/// Align the given address `addr` downwards to alignment `align`.
+///
+/// Requires that `align` is a power of two
+pub fn align_down(addr: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two(), "alignment must be a power of two");
+    addr & !(align - 1)
+}
+
+#[cfg(feature = "alloc-bump")]
+#[test_case]
+fn test_stats_cover_the_heap() {
+    let stats = ALLOCATOR.stats();
+    assert_eq!(stats.used + stats.free, heap_size());
+}
+
+#[test_case]
+fn test_align_up_and_down() {
+    assert_eq!(align_up(0, 8), 0);
+    assert_eq!(align_up(1, 8), 8);
+    assert_eq!(align_up(8, 8), 8);
+    assert_eq!(align_down(15, 8), 8);
+    assert_eq!(align_down(16, 8), 16);
+    assert_eq!(align_up_checked(4097, 4096), Some(8192));
+    for addr in [0, 1, 7, 8, 9, 4095, 4096, 0x4444_4444_0001] {
+        for align in [1, 2, 8, 64, 4096] {
+            let up = align_up(addr, align);
+            // aligning an aligned address is a no-op in both directions
+            assert_eq!(align_down(up, align), up);
+            assert!(up >= addr && up - addr < align);
+            assert!(align_down(addr, align) <= addr);
+        }
+    }
+}
+
+#[test_case]
+fn test_align_up_checked_overflow() {
+    let max_aligned = usize::MAX - 4095;
+    assert_eq!(align_up_checked(max_aligned, 4096), Some(max_aligned));
+    assert_eq!(align_up_checked(max_aligned + 1, 4096), None);
+    assert_eq!(align_up_checked(usize::MAX, 1), Some(usize::MAX));
+    assert_eq!(align_up_checked(usize::MAX, 2), None);
+    // the unchecked version wraps around to 0
+    assert_eq!(align_up(usize::MAX, 2), 0);
+}
+
+/// Odd size and alignment combinations for the alignment tests
+#[cfg(test)]
+const ODD_LAYOUTS: [(usize, usize); 6] = [(3, 64), (1, 1), (5, 2), (7, 16), (24, 128), (9, 256)];
+
+/// Allocate every layout of `ODD_LAYOUTS` at once and check that each block is aligned
+#[cfg(test)]
+fn check_alignments(allocator: &impl GlobalAlloc) {
+    let mut blocks = [null_mut(); ODD_LAYOUTS.len()];
+    for (block, &(size, align)) in blocks.iter_mut().zip(&ODD_LAYOUTS) {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null(), "{} byte allocation failed", size);
+        assert_eq!(
+            *block as usize % align,
+            0,
+            "{} byte block not aligned",
+            size
+        );
+        unsafe { block.write_bytes(0xab, size) };
+    }
+    for (&block, &(size, align)) in blocks.iter().zip(&ODD_LAYOUTS).rev() {
+        unsafe { allocator.dealloc(block, Layout::from_size_align(size, align).unwrap()) };
+    }
+}
+
+/// A heap for one allocator under test, aligned so the buddy allocator gets big blocks
+#[cfg(test)]
+#[repr(align(4096))]
+struct TestHeap([u8; 8192]);
+
+#[test_case]
+fn test_raw_allocation_is_aligned() {
+    let ptr = alloc_raw(3, 64);
+    assert!(!ptr.is_null());
+    assert_eq!(ptr as usize % 64, 0);
+    unsafe { dealloc_raw(ptr, 3, 64) };
+    check_alignments(&GLOBAL_ALLOCATOR);
+}
+
+#[test_case]
+fn test_raw_allocation_rejects_invalid_layouts() {
+    // not a power of two
+    assert!(alloc_raw(8, 3).is_null());
+    assert!(alloc_raw(8, 0).is_null());
+    // rounding up to the alignment would overflow
+    assert!(alloc_raw(usize::MAX, 64).is_null());
+    assert!(alloc_raw(0, 8).is_null());
+}
+
+#[test_case]
+fn test_bump_alignment() {
+    static mut HEAP: TestHeap = TestHeap([0; 8192]);
+    let allocator = Locked::new(bump::BumpAllocator::new());
+    unsafe {
+        let heap = core::ptr::addr_of_mut!(HEAP.0);
+        allocator.lock().init(heap as usize, 8192);
+    }
+    check_alignments(&allocator);
+}
+
+#[test_case]
+fn test_linked_list_alignment() {
+    static mut HEAP: TestHeap = TestHeap([0; 8192]);
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    unsafe {
+        let heap = core::ptr::addr_of_mut!(HEAP.0);
+        allocator.lock().init(heap as usize, 8192);
+    }
+    check_alignments(&allocator);
+}
+
+#[test_case]
+fn test_buddy_alignment() {
+    static mut HEAP: TestHeap = TestHeap([0; 8192]);
+    let allocator = Locked::new(buddy::BuddyAllocator::new());
+    unsafe {
+        let heap = core::ptr::addr_of_mut!(HEAP.0);
+        allocator.lock().init(heap as usize, 8192);
+    }
+    check_alignments(&allocator);
+}
+
+#[test_case]
+fn test_fixed_size_block_alignment() {
+    static mut HEAP: TestHeap = TestHeap([0; 8192]);
+    let allocator = Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+    unsafe {
+        let heap = core::ptr::addr_of_mut!(HEAP.0);
+        allocator.lock().init(heap as usize, 8192);
+    }
+    check_alignments(&allocator);
 }
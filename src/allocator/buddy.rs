@@ -0,0 +1,216 @@
+use crate::allocator::bump::Locked;
+use core::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// Number of block orders, the largest block is `2^(ORDERS - 1)` bytes.
+const ORDERS: usize = 32;
+
+/// The default smallest block is 16 bytes.
+const DEFAULT_MIN_ORDER: usize = 4;
+
+/// A binary buddy allocator.
+///
+/// Every block has a size of `2^order` and is aligned to its size. A block is split
+/// into two halves (buddies) to serve smaller allocations, and merged again with its
+/// buddy when both are free.
+pub struct BuddyAllocator {
+    // free blocks of size `2^order`, indexed by order
+    free_lists: [Option<&'static mut ListNode>; ORDERS],
+    min_order: usize,
+    heap_end: usize,
+}
+
+impl BuddyAllocator {
+    /// Creates an empty BuddyAllocator with the default minimum block order.
+    pub const fn new() -> Self {
+        Self::with_min_order(DEFAULT_MIN_ORDER)
+    }
+
+    /// Creates an empty BuddyAllocator whose smallest blocks are `2^min_order` bytes.
+    ///
+    /// The smallest block must be able to hold a free list node.
+    pub const fn with_min_order(min_order: usize) -> Self {
+        assert!(1 << min_order >= mem::size_of::<ListNode>());
+        assert!(min_order < ORDERS);
+        const EMPTY: Option<&'static mut ListNode> = None;
+        BuddyAllocator {
+            free_lists: [EMPTY; ORDERS],
+            min_order,
+            heap_end: 0,
+        }
+    }
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_region(heap_start, heap_start + heap_size);
+        self.heap_end = heap_start + heap_size;
+    }
+
+    /// Extend the heap by `size` bytes directly after its current end.
+    ///
+    /// This function is unsafe because the caller must guarantee that the memory
+    /// after the heap end is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.add_region(self.heap_end, self.heap_end + size);
+        self.heap_end += size;
+    }
+
+    /// Adds the memory between `start` and `end` to the free lists.
+    unsafe fn add_region(&mut self, start: usize, end: usize) {
+        let min_block = 1 << self.min_order;
+        let mut addr = super::align_up_checked(start, min_block)
+            .expect("heap region at the end of the address space");
+
+        // cover the region with the largest blocks that are aligned to their size
+        while addr + min_block <= end {
+            let mut order = self.min_order;
+            while order + 1 < ORDERS
+                && addr % (1 << (order + 1)) == 0
+                && addr + (1 << (order + 1)) <= end
+            {
+                order += 1;
+            }
+            self.push(order, addr);
+            addr += 1 << order;
+        }
+    }
+
+    /// Returns the order of the block needed for the given layout.
+    fn order_for(&self, layout: &Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(1 << self.min_order)
+            .checked_next_power_of_two()?;
+        let order = size.trailing_zeros() as usize;
+        if order < ORDERS {
+            Some(order)
+        } else {
+            None
+        }
+    }
+
+    /// Adds the block at `addr` to the free list of `order`.
+    unsafe fn push(&mut self, order: usize, addr: usize) {
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(ListNode {
+            next: self.free_lists[order].take(),
+        });
+        self.free_lists[order] = Some(&mut *node_ptr);
+    }
+
+    /// Removes a block from the free list of `order`.
+    fn pop(&mut self, order: usize) -> Option<usize> {
+        let node = self.free_lists[order].take()?;
+        self.free_lists[order] = node.next.take();
+        Some(node as *mut ListNode as usize)
+    }
+
+    /// Removes the block at `addr` from the free list of `order`, if it is there.
+    fn remove(&mut self, order: usize, addr: usize) -> bool {
+        let mut current = &mut self.free_lists[order];
+        while current.is_some() {
+            if current
+                .as_ref()
+                .map(|node| *node as *const ListNode as usize)
+                == Some(addr)
+            {
+                let node = current.take().unwrap();
+                *current = node.next.take();
+                return true;
+            }
+            current = &mut current.as_mut().unwrap().next;
+        }
+        false
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BuddyAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let order = match allocator.order_for(&layout) {
+            Some(order) => order,
+            None => return ptr::null_mut(),
+        };
+
+        // find the smallest free block that is large enough
+        let mut block_order = order;
+        let block = loop {
+            if block_order == ORDERS {
+                return ptr::null_mut(); // out of memory
+            }
+            if let Some(block) = allocator.pop(block_order) {
+                break block;
+            }
+            block_order += 1;
+        };
+
+        // split it until it has the requested size, the upper halves become free blocks
+        while block_order > order {
+            block_order -= 1;
+            allocator.push(block_order, block + (1 << block_order));
+        }
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        let mut order = allocator.order_for(&layout).unwrap();
+        let mut addr = ptr as usize;
+
+        // merge with the buddy as long as it is free
+        while order + 1 < ORDERS {
+            let buddy = addr ^ (1 << order);
+            if !allocator.remove(order, buddy) {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        allocator.push(order, addr);
+    }
+}
+
+#[test_case]
+fn test_buddies_merge_back() {
+    const HEAP_SIZE: usize = 4096;
+    #[repr(align(4096))]
+    struct Heap([u8; HEAP_SIZE]);
+    static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+
+    let allocator = Locked::new(BuddyAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP.0) as usize;
+        allocator.lock().init(heap_start, HEAP_SIZE);
+    }
+
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let medium = Layout::from_size_align(100, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(small);
+        let b = allocator.alloc(medium);
+        let c = allocator.alloc(small);
+        // `c` is the buddy of `a`
+        assert_eq!(c as usize, a as usize ^ 16);
+        assert_eq!(b as usize % 128, 0);
+        allocator.dealloc(a, small);
+        allocator.dealloc(b, medium);
+        allocator.dealloc(c, small);
+    }
+
+    // everything is merged back into the single top order block
+    let mut inner = allocator.lock();
+    for order in 0..12 {
+        assert!(inner.free_lists[order].is_none());
+    }
+    assert!(inner.pop(12).is_some());
+    assert!(inner.pop(12).is_none());
+}
@@ -1,4 +1,4 @@
-use super::align_up;
+use super::{align_up_checked, realloc_by_copy, AllocCounters, AllocStats};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
@@ -29,50 +29,204 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    /// Extend the heap by `size` bytes directly after its current end.
+    ///
+    /// This method is unsafe because the caller must ensure that the memory
+    /// after the heap end is mapped and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    /// Returns the number of bytes between the heap start and the next allocation.
+    pub fn used(&self) -> usize {
+        self.next - self.heap_start
+    }
+
+    /// Returns the number of bytes that can still be allocated.
+    pub fn free(&self) -> usize {
+        self.heap_end - self.next
+    }
+
+    fn capacity(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+}
+
+impl Locked<BumpAllocator> {
+    /// Returns a snapshot of the allocator statistics.
+    ///
+    /// Does not take the lock, see `AllocCounters` for how current it is.
+    pub fn stats(&self) -> AllocStats {
+        self.counters.snapshot()
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut bump = self.lock(); // get a mutable reference
 
-        let alloc_start = align_up(bump.next, layout.align());
+        let alloc_start = match align_up_checked(bump.next, layout.align()) {
+            Some(start) => start,
+            None => return ptr::null_mut(), // calculation overflow
+        };
         let alloc_end = match alloc_start.checked_add(layout.size()) {
             Some(end) => end,
             None => return ptr::null_mut(), // calculation overflow
         };
 
         if alloc_end > bump.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
+            return ptr::null_mut(); // out of memory
         }
+        let used_before = bump.used();
+        bump.next = alloc_end;
+        bump.allocations += 1;
+        let (used_after, capacity) = (bump.used(), bump.capacity());
+        drop(bump);
+
+        self.counters.record(1, used_before, used_after, capacity);
+        alloc_start as *mut u8
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
         let mut bump = self.lock();
 
+        let used_before = bump.used();
         bump.allocations -= 1;
         if bump.allocations == 0 {
             bump.next = bump.heap_start;
         }
+        let (used_after, capacity) = (bump.used(), bump.capacity());
+        drop(bump);
+
+        self.counters.record(-1, used_before, used_after, capacity);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        {
+            let mut bump = self.lock();
+            let start = ptr as usize;
+            // the most recent allocation can be resized by moving `next`
+            if start + layout.size() == bump.next {
+                if let Some(new_end) = start.checked_add(new_size) {
+                    if new_end <= bump.heap_end {
+                        let used_before = bump.used();
+                        bump.next = new_end;
+                        let (used_after, capacity) = (bump.used(), bump.capacity());
+                        drop(bump);
+
+                        self.counters.record(0, used_before, used_after, capacity);
+                        return ptr;
+                    }
+                }
+            }
+        }
+        realloc_by_copy(self, ptr, layout, new_size)
     }
 }
 
 /// A wrapper around spin::Mutex to permit trait implementation
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
+    // statistics readable without the lock, only updated by allocators that keep them
+    pub(super) counters: AllocCounters,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
             inner: spin::Mutex::new(inner),
+            counters: AllocCounters::new(),
         }
     }
 
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<'_, A>> {
+        self.inner.try_lock()
+    }
+}
+
+#[test_case]
+fn test_bump_stats_peak_used() {
+    const HEAP_SIZE: usize = 1024;
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        allocator.lock().init(heap_start, HEAP_SIZE);
+    }
+
+    let layout = Layout::from_size_align(100, 1).unwrap();
+    unsafe {
+        let a = allocator.alloc(layout);
+        let b = allocator.alloc(layout);
+        assert_eq!(allocator.stats().used, 200);
+        allocator.dealloc(a, layout);
+        allocator.dealloc(b, layout);
+        // all allocations are freed, so the heap is reused from the start
+        let c = allocator.alloc(layout);
+        let stats = allocator.stats();
+        assert_eq!(stats.used, 100);
+        assert_eq!(stats.free, HEAP_SIZE - 100);
+        assert_eq!(stats.peak_used, 200);
+        assert_eq!(stats.allocations, 1);
+        allocator.dealloc(c, layout);
+    }
+}
+
+#[test_case]
+fn test_bump_stats_while_locked() {
+    const HEAP_SIZE: usize = 1024;
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+    static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
+
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        ALLOCATOR.lock().init(heap_start, HEAP_SIZE);
+    }
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+
+    // like a timer interrupt that reads the statistics in the middle of an allocation,
+    // with the old lock based `stats` this never returned
+    let guard = ALLOCATOR.lock();
+    let stats = ALLOCATOR.stats();
+    drop(guard);
+    assert_eq!(stats.used, 64);
+    assert_eq!(stats.free, HEAP_SIZE - 64);
+    assert_eq!(stats.allocations, 1);
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert_eq!(ALLOCATOR.stats().allocations, 0);
+    assert_eq!(ALLOCATOR.stats().used, 0);
+}
+
+#[test_case]
+fn test_bump_realloc_in_place() {
+    const HEAP_SIZE: usize = 1024;
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        allocator.lock().init(heap_start, HEAP_SIZE);
+    }
+
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    unsafe {
+        let a = allocator.alloc(layout);
+        // the last allocation grows in place
+        assert_eq!(allocator.realloc(a, layout, 64), a);
+        let grown = Layout::from_size_align(64, 1).unwrap();
+        let b = allocator.alloc(layout);
+        // `a` is not the last allocation anymore, so it is moved
+        let moved = allocator.realloc(a, grown, 128);
+        assert_ne!(moved, a);
+        allocator.dealloc(b, layout);
+        allocator.dealloc(moved, Layout::from_size_align(128, 1).unwrap());
+    }
 }
@@ -0,0 +1,98 @@
+use super::align_up_checked;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A bump allocator over a fixed array in the kernel image, so it works before
+/// paging is set up for the heap.
+///
+/// It can hand out at most `N` bytes over the whole uptime: frees are ignored and
+/// the memory is never reused, even after the real heap took over. So it is only
+/// meant for a few small allocations during early boot.
+pub struct EarlyAllocator<const N: usize> {
+    memory: UnsafeCell<[u8; N]>,
+    // offset of the next free byte in `memory`
+    next: AtomicUsize,
+}
+
+// `next` hands out every byte of `memory` only once
+unsafe impl<const N: usize> Sync for EarlyAllocator<N> {}
+
+impl<const N: usize> EarlyAllocator<N> {
+    pub const fn new() -> Self {
+        EarlyAllocator {
+            memory: UnsafeCell::new([0; N]),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn start(&self) -> usize {
+        self.memory.get() as usize
+    }
+
+    /// Whether `ptr` points into the memory of this allocator
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        (self.start()..self.start() + N).contains(&(ptr as usize))
+    }
+
+    /// Returns the number of bytes handed out so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.next.load(Ordering::Relaxed)
+    }
+}
+
+impl<const N: usize> Default for EarlyAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for EarlyAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let start = self.start();
+        let mut alloc_start = 0;
+        // no lock, so it is also safe to allocate from interrupt handlers
+        let result = self
+            .next
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |next| {
+                alloc_start = align_up_checked(start + next, layout.align())?;
+                let alloc_end = alloc_start.checked_add(layout.size())?;
+                if alloc_end > start + N {
+                    None // out of memory
+                } else {
+                    Some(alloc_end - start)
+                }
+            });
+        match result {
+            Ok(_) => alloc_start as *mut u8,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        // the memory is never reused
+    }
+}
+
+#[test_case]
+fn test_early_allocator_alignment_and_limit() {
+    let allocator: EarlyAllocator<64> = EarlyAllocator::new();
+    let byte = Layout::from_size_align(1, 1).unwrap();
+    let word = Layout::from_size_align(8, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(byte);
+        let b = allocator.alloc(word);
+        assert!(allocator.contains(a) && allocator.contains(b));
+        assert_eq!(b as usize % 8, 0);
+        b.cast::<u64>().write(0x1234);
+        allocator.dealloc(a, byte);
+        // freeing does not give the memory back
+        assert_ne!(allocator.alloc(byte), a);
+        assert_eq!(b.cast::<u64>().read(), 0x1234);
+
+        let too_large = Layout::from_size_align(64, 1).unwrap();
+        assert!(allocator.alloc(too_large).is_null());
+    }
+    assert!(allocator.used() <= 64);
+}
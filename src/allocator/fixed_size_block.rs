@@ -1,4 +1,6 @@
+use super::realloc_by_copy;
 use crate::allocator::bump::Locked;
+use crate::serial_println;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use core::{mem, ptr};
@@ -16,6 +18,11 @@ const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    // number of allocations served by each block size
+    block_hits: [u64; BLOCK_SIZES.len()],
+    // number of allocations that went to the fallback allocator, either because
+    // they are too large for a block or because the block list was empty
+    fallback_allocs: u64,
 }
 
 impl FixedSizeBlockAllocator {
@@ -25,6 +32,8 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            block_hits: [0; BLOCK_SIZES.len()],
+            fallback_allocs: 0,
         }
     }
 
@@ -37,8 +46,27 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    /// Extend the heap by `size` bytes directly after its current end.
+    ///
+    /// The new memory goes to the fallback allocator, the block lists fill up from it
+    /// as usual. This function is unsafe because the caller must guarantee that the
+    /// memory after the heap end is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.fallback_allocator.extend(size);
+    }
+
+    /// Print the number of allocations per block size to serial, to help tuning `BLOCK_SIZES`.
+    pub fn report(&self) {
+        serial_println!("fixed size block allocator:");
+        for (size, hits) in BLOCK_SIZES.iter().zip(self.block_hits.iter()) {
+            serial_println!("  {:>4} bytes: {}", size, hits);
+        }
+        serial_println!("  fallback: {}", self.fallback_allocs);
+    }
+
     /// Allocates using the fallback allocator.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocs += 1;
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
@@ -51,6 +79,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
         let mut allocator = self.lock();
         match list_index(&layout) {
             Some(index) => {
+                allocator.block_hits[index] += 1;
                 match allocator.list_heads[index].take() {
                     Some(node) => {
                         allocator.list_heads[index] = node.next.take();
@@ -90,6 +119,15 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             }
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // the block is large enough as long as the size class stays the same
+        match list_index(&layout) {
+            Some(index) if list_index(&new_layout) == Some(index) => ptr,
+            _ => realloc_by_copy(self, ptr, layout, new_size),
+        }
+    }
 }
 
 /// Choose an appropriate block size for the given layout.
@@ -99,3 +137,53 @@ fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
+
+#[test_case]
+fn test_vec_growth_keeps_block() {
+    use alloc::vec::Vec;
+
+    let mut vec: Vec<u8> = Vec::with_capacity(2);
+    vec.push(1);
+    let ptr = vec.as_ptr();
+    // growing to 8 elements stays in the 8 byte block
+    for i in 0..7 {
+        vec.push(i);
+    }
+    assert!(vec.capacity() <= 8);
+    assert_eq!(vec.as_ptr(), ptr);
+}
+
+#[test_case]
+fn test_allocation_counters() {
+    const HEAP_SIZE: usize = 8 * 1024;
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        allocator.lock().init(heap_start, HEAP_SIZE);
+    }
+
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let medium = Layout::from_size_align(100, 8).unwrap();
+    let large = Layout::from_size_align(4096, 8).unwrap();
+    unsafe {
+        // the first block of a size comes from the fallback allocator
+        let ptr = allocator.alloc(small);
+        allocator.dealloc(ptr, small);
+        // the second one is taken from the block list
+        let ptr = allocator.alloc(small);
+        allocator.dealloc(ptr, small);
+        let ptr = allocator.alloc(medium);
+        allocator.dealloc(ptr, medium);
+        let ptr = allocator.alloc(large);
+        allocator.dealloc(ptr, large);
+    }
+
+    let inner = allocator.lock();
+    assert_eq!(inner.block_hits[0], 2);
+    assert_eq!(inner.block_hits[list_index(&medium).unwrap()], 1);
+    assert_eq!(inner.block_hits.iter().sum::<u64>(), 3);
+    assert_eq!(inner.fallback_allocs, 3);
+    inner.report();
+}
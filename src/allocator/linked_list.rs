@@ -1,4 +1,4 @@
-use super::align_up;
+use super::{align_up, align_up_checked, realloc_by_copy};
 use crate::allocator::bump::Locked;
 use core::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
@@ -24,6 +24,7 @@ impl ListNode {
 
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_end: usize,
 }
 
 impl LinkedListAllocator {
@@ -31,6 +32,7 @@ impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: ListNode::new(0),
+            heap_end: 0,
         }
     }
 
@@ -41,20 +43,59 @@ impl LinkedListAllocator {
     /// called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.add_free_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
     }
 
-    /// Adds the given memory region to the front of the list
+    /// Extend the heap by `size` bytes directly after its current end.
+    ///
+    /// This function is unsafe because the caller must guarantee that the memory
+    /// after the heap end is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        // merges with the last free region if that one reaches up to the old end
+        self.add_free_region(self.heap_end, size);
+        self.heap_end += size;
+    }
+
+    /// Adds the given memory region to the list, which is kept sorted by address.
+    ///
+    /// The region is merged with the free regions directly before and after it,
+    /// so that freed neighbours can serve larger allocations again.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensures that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
+        // find the last region that starts before the new one (or the head)
+        let mut prev = &mut self.head;
+        while prev
+            .next
+            .as_ref()
+            .map_or(false, |next| next.start_addr() < addr)
+        {
+            prev = prev.next.as_mut().unwrap();
+        }
+
+        // merge with the following region
+        let mut size = size;
+        let mut next = prev.next.take();
+        if let Some(ref mut following) = next {
+            if addr + size == following.start_addr() {
+                size += following.size;
+                next = following.next.take();
+            }
+        }
+
+        // merge with the preceding region, the head has size 0 and is never merged
+        if prev.size > 0 && prev.end_addr() == addr {
+            prev.size += size;
+            prev.next = next;
+        } else {
+            let mut node = ListNode::new(size);
+            node.next = next;
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(node);
+            prev.next = Some(&mut *node_ptr)
+        }
     }
 
     /// Looks for a free region with the given size and alignment and removes
@@ -86,7 +127,7 @@ impl LinkedListAllocator {
     ///
     /// Returns the allocation start address on success.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let alloc_start = align_up_checked(region.start_addr(), align).ok_or(())?;
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -121,6 +162,50 @@ impl LinkedListAllocator {
     }
 }
 
+/// How the free memory of a `LinkedListAllocator` is split up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragStats {
+    /// Number of regions in the free list
+    pub free_blocks: usize,
+    /// Size of the largest free region, the largest allocation that can succeed
+    pub largest_free_block: usize,
+    /// Bytes in all free regions together
+    pub free_bytes: usize,
+}
+
+impl FragStats {
+    /// Share of the free memory outside of the largest free region in percent: 0 when
+    /// all free memory is in one piece, close to 100 when it is split into many small ones
+    pub fn fragmentation_percent(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        (self.free_bytes - self.largest_free_block) * 100 / self.free_bytes
+    }
+}
+
+impl Locked<LinkedListAllocator> {
+    /// Walk the free list and report how fragmented it is.
+    ///
+    /// Holds the allocator lock for the whole walk.
+    pub fn fragmentation(&self) -> FragStats {
+        let allocator = self.lock();
+        let mut stats = FragStats {
+            free_blocks: 0,
+            largest_free_block: 0,
+            free_bytes: 0,
+        };
+        let mut current = allocator.head.next.as_deref();
+        while let Some(region) = current {
+            stats.free_blocks += 1;
+            stats.largest_free_block = stats.largest_free_block.max(region.size);
+            stats.free_bytes += region.size;
+            current = region.next.as_deref();
+        }
+        stats
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // perform layout adjustment
@@ -145,4 +230,94 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
 
         self.lock().add_free_region(ptr as usize, size)
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // the region is reused if the size adjustment rounds both layouts to the same size
+        if LinkedListAllocator::size_align(layout) == LinkedListAllocator::size_align(new_layout) {
+            ptr
+        } else {
+            realloc_by_copy(self, ptr, layout, new_size)
+        }
+    }
+}
+
+#[test_case]
+fn test_adjacent_free_regions_are_merged() {
+    // an `u64` array is aligned for `ListNode`
+    static mut HEAP: [u64; 32] = [0; 32];
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        allocator.lock().init(heap_start, 256);
+    }
+
+    let block = Layout::from_size_align(96, 8).unwrap();
+    let rest = Layout::from_size_align(64, 8).unwrap();
+    let large = Layout::from_size_align(160, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(block);
+        let b = allocator.alloc(block);
+        let c = allocator.alloc(rest);
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+        // the heap is full, and 160 bytes are more than one freed block
+        allocator.dealloc(a, block);
+        allocator.dealloc(b, block);
+        let merged = allocator.alloc(large);
+        assert!(!merged.is_null());
+        allocator.dealloc(merged, large);
+        allocator.dealloc(c, rest);
+        // everything is merged back into a single region
+        let inner = allocator.lock();
+        let node = inner.head.next.as_ref().unwrap();
+        assert_eq!(node.size, 256);
+        assert!(node.next.is_none());
+    }
+}
+
+#[test_case]
+fn test_fragmentation_report() {
+    static mut HEAP: [u64; 32] = [0; 32];
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        let heap_start = core::ptr::addr_of_mut!(HEAP) as usize;
+        allocator.lock().init(heap_start, 256);
+    }
+    let whole = FragStats {
+        free_blocks: 1,
+        largest_free_block: 256,
+        free_bytes: 256,
+    };
+    assert_eq!(allocator.fragmentation(), whole);
+    assert_eq!(whole.fragmentation_percent(), 0);
+
+    let block = Layout::from_size_align(32, 8).unwrap();
+    unsafe {
+        let blocks: [*mut u8; 8] = core::array::from_fn(|_| allocator.alloc(block));
+        assert!(blocks.iter().all(|block| !block.is_null()));
+        assert_eq!(allocator.fragmentation().free_blocks, 0);
+
+        // every other block is free, none of them are neighbours
+        for &ptr in blocks.iter().step_by(2) {
+            allocator.dealloc(ptr, block);
+        }
+        let stats = allocator.fragmentation();
+        assert_eq!(stats.free_blocks, 4);
+        assert_eq!(stats.largest_free_block, 32);
+        assert_eq!(stats.free_bytes, 128);
+        assert_eq!(stats.fragmentation_percent(), 75);
+
+        // freeing the block between the first two free ones merges all three
+        allocator.dealloc(blocks[1], block);
+        let stats = allocator.fragmentation();
+        assert_eq!(stats.free_blocks, 3);
+        assert_eq!(stats.largest_free_block, 96);
+
+        for &ptr in blocks.iter().skip(3).step_by(2) {
+            allocator.dealloc(ptr, block);
+        }
+    }
+    assert_eq!(allocator.fragmentation(), whole);
 }
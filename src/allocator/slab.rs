@@ -0,0 +1,148 @@
+use alloc::alloc::{alloc, dealloc};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+/// Size of the slabs requested from the global allocator.
+const SLAB_SIZE: usize = 4096;
+
+struct FreeSlot {
+    next: Option<NonNull<FreeSlot>>,
+}
+
+/// A pool of equally sized slots for objects of type `T`.
+///
+/// Slots come from slabs of `SLAB_SIZE` bytes that are requested from the
+/// global allocator when the free list runs empty, so `alloc` and `free` are
+/// O(1) apart from growing. Slabs are only returned when the cache is dropped.
+pub struct SlabCache<T> {
+    free_list: Option<NonNull<FreeSlot>>,
+    slabs: Vec<NonNull<u8>>,
+    _marker: PhantomData<T>,
+}
+
+// the cache owns its slabs, sharing it still needs a lock like `Locked`
+unsafe impl<T: Send> Send for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    /// Creates an empty SlabCache, no memory is requested until the first `alloc`.
+    pub const fn new() -> Self {
+        SlabCache {
+            free_list: None,
+            slabs: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Layout of a single slot, large enough for a `T` or a free list node.
+    fn slot_layout() -> Layout {
+        let size = mem::size_of::<T>().max(mem::size_of::<FreeSlot>());
+        let align = mem::align_of::<T>().max(mem::align_of::<FreeSlot>());
+        Layout::from_size_align(size, align).unwrap().pad_to_align()
+    }
+
+    fn slab_layout() -> Layout {
+        let slot = Self::slot_layout();
+        Layout::from_size_align(SLAB_SIZE.max(slot.size()), slot.align()).unwrap()
+    }
+
+    /// Number of slots in each slab.
+    pub fn slots_per_slab() -> usize {
+        Self::slab_layout().size() / Self::slot_layout().size()
+    }
+
+    /// Number of slabs requested from the global allocator so far.
+    pub fn slab_count(&self) -> usize {
+        self.slabs.len()
+    }
+
+    /// Returns an uninitialized slot for a `T`, or a null pointer if a new
+    /// slab was needed and the global allocator is out of memory.
+    pub fn alloc(&mut self) -> *mut T {
+        if self.free_list.is_none() && !self.grow() {
+            return ptr::null_mut();
+        }
+        match self.free_list {
+            Some(slot) => {
+                self.free_list = unsafe { slot.as_ref().next };
+                slot.as_ptr() as *mut T
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Puts a slot back on the free list.
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was returned by `alloc` of this cache and is not used anymore. The
+    /// value in the slot is not dropped.
+    pub unsafe fn free(&mut self, ptr: *mut T) {
+        let slot = ptr as *mut FreeSlot;
+        slot.write(FreeSlot {
+            next: self.free_list,
+        });
+        self.free_list = NonNull::new(slot);
+    }
+
+    /// Requests a new slab and carves it into slots on the free list.
+    fn grow(&mut self) -> bool {
+        let slab = match NonNull::new(unsafe { alloc(Self::slab_layout()) }) {
+            Some(slab) => slab,
+            None => return false,
+        };
+        self.slabs.push(slab);
+
+        let slot_size = Self::slot_layout().size();
+        // push in reverse so the slots are handed out in address order
+        for index in (0..Self::slots_per_slab()).rev() {
+            unsafe { self.free(slab.as_ptr().add(index * slot_size) as *mut T) };
+        }
+        true
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    fn drop(&mut self) {
+        for slab in self.slabs.drain(..) {
+            unsafe { dealloc(slab.as_ptr(), Self::slab_layout()) };
+        }
+    }
+}
+
+#[test_case]
+fn test_slab_cache_grows() {
+    let mut cache: SlabCache<[u64; 4]> = SlabCache::new();
+    let count = SlabCache::<[u64; 4]>::slots_per_slab() + 1;
+    let mut objects = Vec::new();
+    for i in 0..count {
+        let ptr = cache.alloc();
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % mem::align_of::<[u64; 4]>(), 0);
+        unsafe { ptr.write([i as u64; 4]) };
+        objects.push(ptr);
+    }
+    assert_eq!(cache.slab_count(), 2);
+    for (i, &ptr) in objects.iter().enumerate() {
+        assert_eq!(unsafe { ptr.read() }, [i as u64; 4]);
+    }
+}
+
+#[test_case]
+fn test_slab_cache_reuses_freed_slots() {
+    let mut cache: SlabCache<u32> = SlabCache::new();
+    let first = cache.alloc();
+    let second = cache.alloc();
+    unsafe {
+        cache.free(first);
+        cache.free(second);
+    }
+    // the free list is LIFO
+    assert_eq!(cache.alloc(), second);
+    assert_eq!(cache.alloc(), first);
+    for _ in 2..SlabCache::<u32>::slots_per_slab() {
+        cache.alloc();
+    }
+    assert_eq!(cache.slab_count(), 1);
+}
@@ -0,0 +1,240 @@
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::{memory, msr};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+// the address bits of IA32_APIC_BASE
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// the I/O APIC is usually here, the real address would come from the ACPI tables
+const IO_APIC_PHYS_ADDRESS: u64 = 0xFEC0_0000;
+
+// where the register pages are mapped
+const LOCAL_APIC_VIRT_ADDRESS: u64 = 0x_4444_8888_0000;
+const IO_APIC_VIRT_ADDRESS: u64 = 0x_4444_8888_1000;
+
+// local APIC registers, as offsets from the base
+const LAPIC_ID: usize = 0x20;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SPURIOUS: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3E0;
+
+// bit 8 of the spurious interrupt vector register software-enables the local APIC
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+// LVT bits: the interrupt is not delivered / periodic timer mode
+const LAPIC_LVT_MASKED: u32 = 1 << 16;
+const LAPIC_TIMER_PERIODIC: u32 = 1 << 17;
+// divide the bus clock by 16
+const LAPIC_TIMER_DIVIDE_BY_16: u32 = 0b0011;
+// interrupt command delivery modes, INIT is sent with the level asserted
+const ICR_INIT: u32 = 0b101 << 8 | 1 << 14;
+const ICR_STARTUP: u32 = 0b110 << 8 | 1 << 14;
+// set while the local APIC has not sent the last IPI yet
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+/// Initial count of the periodic timer if it can not be calibrated.
+///
+/// The tick rate then depends on the bus clock and does not match `time::pit_frequency`.
+const LAPIC_TIMER_COUNT: u32 = 0x0010_0000;
+// PIT ticks the local APIC timer is measured against
+const CALIBRATION_TICKS: u32 = 5;
+
+/// Vector of the spurious interrupts the local APIC may send, they need no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// I/O APIC registers, IOWIN accesses the register selected by IOREGSEL
+const IO_APIC_IOREGSEL: usize = 0x00;
+const IO_APIC_IOWIN: usize = 0x10;
+// the redirection entry of IRQ n is in registers 0x10 + 2n (low) and 0x11 + 2n (high)
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+
+// the keyboard is ISA IRQ 1 and COM1 IRQ 4, QEMU does not remap them
+const KEYBOARD_IRQ: u32 = 1;
+const COM1_IRQ: u32 = 4;
+
+// virtual address of the local APIC registers, 0 while the 8259 PIC is used
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// CPUID does not report a local APIC or MSRs
+    Unsupported,
+    /// Mapping the register pages failed
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for ApicError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        ApicError::Map(error)
+    }
+}
+
+/// Whether interrupts are delivered by the APIC instead of the 8259 PIC
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.load(Ordering::SeqCst) != 0
+}
+
+/// Switch from the 8259 PIC to the local and I/O APIC.
+///
+/// Masks the PIC, then routes the APIC timer, the keyboard and COM1 to the same
+/// vectors the PIC used, so the existing handlers keep working. Has to be
+/// called after `init`, once the memory management is set up.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), ApicError> {
+    let apic_base = msr::read_apic_base().ok_or(ApicError::Unsupported)?;
+    let local_apic_phys = apic_base & APIC_BASE_ADDRESS_MASK;
+    map_registers(
+        local_apic_phys,
+        LOCAL_APIC_VIRT_ADDRESS,
+        mapper,
+        frame_allocator,
+    )?;
+    map_registers(
+        IO_APIC_PHYS_ADDRESS,
+        IO_APIC_VIRT_ADDRESS,
+        mapper,
+        frame_allocator,
+    )?;
+    // still with the PIT, which the calibration counts ticks of
+    let timer_count = unsafe { calibrate_timer(LOCAL_APIC_VIRT_ADDRESS as usize) };
+
+    interrupts::without_interrupts(|| {
+        // the PIC would otherwise still deliver interrupts on the same vectors
+        unsafe { PICS.lock().disable() };
+
+        let local_apic = LOCAL_APIC_VIRT_ADDRESS as usize;
+        unsafe {
+            write_register(
+                local_apic,
+                LAPIC_SPURIOUS,
+                LAPIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR),
+            );
+            write_register(
+                local_apic,
+                LAPIC_LVT_TIMER,
+                LAPIC_TIMER_PERIODIC | u32::from(InterruptIndex::Timer.as_u8()),
+            );
+            // writing the initial count starts the timer
+            write_register(local_apic, LAPIC_TIMER_INITIAL_COUNT, timer_count);
+
+            // deliver the keyboard and COM1 to this CPU, fixed delivery, edge triggered, unmasked
+            let apic_id = read_register(local_apic, LAPIC_ID) >> 24;
+            route_irq(KEYBOARD_IRQ, InterruptIndex::Keyboard, apic_id);
+            route_irq(COM1_IRQ, InterruptIndex::Com1, apic_id);
+        }
+        LOCAL_APIC.store(LOCAL_APIC_VIRT_ADDRESS, Ordering::SeqCst);
+    });
+
+    Ok(())
+}
+
+/// The initial count that makes the local APIC timer tick as often as the PIT.
+///
+/// Counts down from the maximum for `CALIBRATION_TICKS` PIT ticks with the timer
+/// interrupt masked. Needs the PIT interrupt enabled, without it the timer stays at
+/// `LAPIC_TIMER_COUNT`. This function is unsafe because the local APIC registers
+/// must be mapped at `local_apic`.
+unsafe fn calibrate_timer(local_apic: usize) -> u32 {
+    write_register(local_apic, LAPIC_TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+    if !interrupts::are_enabled() {
+        log::warn!("interrupts are disabled, the APIC timer is not calibrated");
+        return LAPIC_TIMER_COUNT;
+    }
+    write_register(
+        local_apic,
+        LAPIC_LVT_TIMER,
+        LAPIC_LVT_MASKED | u32::from(InterruptIndex::Timer.as_u8()),
+    );
+    // start right after a tick, so the measurement covers whole ticks
+    wait_for_ticks(1);
+    write_register(local_apic, LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+    wait_for_ticks(u64::from(CALIBRATION_TICKS));
+    let elapsed = u32::MAX - read_register(local_apic, LAPIC_TIMER_CURRENT_COUNT);
+    // stop it until `init` starts it in periodic mode
+    write_register(local_apic, LAPIC_TIMER_INITIAL_COUNT, 0);
+    (elapsed / CALIBRATION_TICKS).max(1)
+}
+
+fn wait_for_ticks(ticks: u64) {
+    let end = crate::interrupts::ticks() + ticks;
+    while crate::interrupts::ticks() < end {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Signal the end of the current interrupt to the local APIC
+pub fn end_of_interrupt() {
+    let local_apic = LOCAL_APIC.load(Ordering::SeqCst) as usize;
+    unsafe { write_register(local_apic, LAPIC_EOI, 0) };
+}
+
+/// APIC id of the CPU this runs on, requires `init`
+pub fn local_apic_id() -> u8 {
+    let local_apic = LOCAL_APIC.load(Ordering::SeqCst) as usize;
+    (unsafe { read_register(local_apic, LAPIC_ID) } >> 24) as u8
+}
+
+/// Send an INIT IPI, which resets the CPU with `apic_id` to wait for a startup IPI.
+///
+/// Requires `init`.
+pub fn send_init_ipi(apic_id: u8) {
+    unsafe { send_ipi(apic_id, ICR_INIT) };
+}
+
+/// Send a startup IPI, the CPU with `apic_id` starts in real mode at `page * 4096`.
+///
+/// Only CPUs waiting after an INIT IPI react to it. Requires `init`.
+pub fn send_startup_ipi(apic_id: u8, page: u8) {
+    unsafe { send_ipi(apic_id, ICR_STARTUP | u32::from(page)) };
+}
+
+unsafe fn send_ipi(apic_id: u8, command: u32) {
+    let local_apic = LOCAL_APIC.load(Ordering::SeqCst) as usize;
+    write_register(local_apic, LAPIC_ICR_HIGH, u32::from(apic_id) << 24);
+    // writing the low half sends the IPI
+    write_register(local_apic, LAPIC_ICR_LOW, command);
+    while read_register(local_apic, LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn map_registers(
+    phys_address: u64,
+    virt_address: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(virt_address));
+    let frame = PhysFrame::containing_address(PhysAddr::new(phys_address));
+    // the frame holds the registers of an APIC, which only this module accesses
+    unsafe { memory::map_mmio(page, frame, mapper, frame_allocator) }
+}
+
+unsafe fn read_register(base: usize, offset: usize) -> u32 {
+    ((base + offset) as *const u32).read_volatile()
+}
+
+unsafe fn write_register(base: usize, offset: usize, value: u32) {
+    ((base + offset) as *mut u32).write_volatile(value)
+}
+
+unsafe fn route_irq(irq: u32, index: InterruptIndex, apic_id: u32) {
+    let entry = IO_APIC_REDIRECTION_TABLE + 2 * irq;
+    write_io_apic(entry, u32::from(index.as_u8()));
+    write_io_apic(entry + 1, apic_id << 24);
+}
+
+unsafe fn write_io_apic(register: u32, value: u32) {
+    let io_apic = IO_APIC_VIRT_ADDRESS as usize;
+    write_register(io_apic, IO_APIC_IOREGSEL, register);
+    write_register(io_apic, IO_APIC_IOWIN, value);
+}
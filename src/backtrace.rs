@@ -0,0 +1,66 @@
+use crate::{println, serial_println};
+use core::arch::asm;
+
+/// Stop after this many frames in case the chain of frame pointers loops
+const MAX_FRAMES: usize = 32;
+/// Frames further than this above the first one are not on our stack anymore
+const MAX_STACK_SPAN: u64 = 1024 * 1024;
+
+/// Call `f` with the index and return address of each frame on the current call stack.
+///
+/// This relies on frame pointers, which the target spec forces on: every
+/// frame starts with the previous `rbp` followed by the return address.
+/// The walk stops at the first `rbp` that does not look like it points into
+/// the stack, because dereferencing a garbage pointer would fault again.
+#[inline(never)]
+pub fn for_each_frame(mut f: impl FnMut(usize, u64)) {
+    let mut rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    let stack_start = rbp;
+
+    for index in 0..MAX_FRAMES {
+        // the stack grows down, so callers have their frames at higher addresses
+        let plausible =
+            rbp != 0 && rbp % 8 == 0 && rbp >= stack_start && rbp - stack_start < MAX_STACK_SPAN;
+        if !plausible {
+            break;
+        }
+        let frame = rbp as *const u64;
+        let (previous_rbp, return_address) = unsafe { (*frame, *frame.add(1)) };
+        if return_address == 0 {
+            break;
+        }
+        f(index, return_address);
+        // frames must strictly move up the stack, otherwise the chain is broken
+        if previous_rbp <= rbp {
+            break;
+        }
+        rbp = previous_rbp;
+    }
+}
+
+/// Print the return addresses of the current call stack to the screen and serial.
+///
+/// The addresses can be resolved offline with the linker map or `addr2line`.
+pub fn print_backtrace() {
+    println!("Backtrace:");
+    serial_println!("Backtrace:");
+    for_each_frame(|index, address| {
+        println!("#{}  {:#x}", index, address);
+        serial_println!("#{}  {:#x}", index, address);
+    });
+}
+
+#[test_case]
+fn test_backtrace_has_frames() {
+    let mut frames = 0;
+    let mut last_address = 0;
+    for_each_frame(|index, address| {
+        assert_eq!(index, frames);
+        frames += 1;
+        last_address = address;
+    });
+    // at least this test function called `for_each_frame`
+    assert!(frames >= 1);
+    assert_ne!(last_address, 0);
+}
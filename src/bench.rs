@@ -0,0 +1,85 @@
+use crate::{serial_print, serial_println, Testable};
+use core::arch::x86_64::_rdtsc;
+
+/// Run `f` `iters` times and return the average number of TSC cycles per run.
+///
+/// Under QEMU the TSC is emulated, so the numbers are only rough.
+pub fn cycles_per_iter<F: Fn()>(iters: u64, f: F) -> u64 {
+    let start = unsafe { _rdtsc() };
+    for _ in 0..iters {
+        f();
+    }
+    let end = unsafe { _rdtsc() };
+    end.wrapping_sub(start) / iters.max(1)
+}
+
+/// Run `f` `iters` times and print the cycles per iteration over serial
+pub fn time_it<F: Fn()>(name: &str, iters: u64, f: F) {
+    let cycles = cycles_per_iter(iters, f);
+    serial_println!("{}: {} cycles/iter ({} iters)", name, cycles, iters);
+}
+
+/// A benchmark that can be run by the test runner like a `#[test_case]`.
+///
+/// Use the `bench!` macro to register one.
+pub struct Bench {
+    name: &'static str,
+    iters: u64,
+    f: fn(),
+}
+
+impl Bench {
+    pub const fn new(name: &'static str, iters: u64, f: fn()) -> Bench {
+        Bench { name, iters, f }
+    }
+}
+
+impl Testable for Bench {
+    fn run(&self) -> () {
+        serial_print!("{}...\t", self.name);
+        crate::arm_test_timeout(self.name);
+        let cycles = cycles_per_iter(self.iters, self.f);
+        crate::disarm_test_timeout();
+        serial_println!("[bench] {} cycles/iter ({} iters)", cycles, self.iters);
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Register a benchmark with the test runner
+///
+/// ```ignore
+/// bench! {
+///     fn bench_something(1000) {
+///         do_something();
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! bench {
+    (fn $name:ident($iters:expr) $body:block) => {
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        static $name: $crate::bench::Bench =
+            $crate::bench::Bench::new(concat!(module_path!(), "::", stringify!($name)), $iters, {
+                fn $name() $body
+                $name
+            });
+    };
+}
+
+#[cfg(test)]
+bench! {
+    fn bench_box_new_drop(1000) {
+        let value = alloc::boxed::Box::new(41);
+        // keep the allocation from being optimized away
+        core::hint::black_box(&value);
+    }
+}
+
+#[test_case]
+fn test_cycles_per_iter_counts() {
+    assert!(cycles_per_iter(10, || core::hint::black_box(())) > 0);
+}
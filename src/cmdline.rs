@@ -0,0 +1,137 @@
+use heapless::Vec;
+use log::LevelFilter;
+
+/// Maximum number of arguments kept, later ones are ignored
+pub const MAX_ARGS: usize = 16;
+
+/// The kernel command line.
+///
+/// The boot info of `bootloader` 0.9 has no command line, so it is taken
+/// from the `KERNEL_CMDLINE` environment variable at build time for now.
+pub const BOOT_CMDLINE: &str = match option_env!("KERNEL_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// A single `key=value` or bare `flag` token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Arg<'a> {
+    key: &'a str,
+    value: Option<&'a str>,
+}
+
+/// Parsed command line arguments, borrowing from the raw string
+#[derive(Debug)]
+pub struct CmdlineArgs<'a> {
+    args: Vec<Arg<'a>, MAX_ARGS>,
+}
+
+impl<'a> CmdlineArgs<'a> {
+    /// Value of `key`, if the same key is given several times the last one wins
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.args
+            .iter()
+            .rev()
+            .find(|arg| arg.key == key && arg.value.is_some())
+            .and_then(|arg| arg.value)
+    }
+
+    /// Whether the bare flag `name` is given
+    pub fn flag(&self, name: &str) -> bool {
+        self.args
+            .iter()
+            .any(|arg| arg.key == name && arg.value.is_none())
+    }
+
+    /// Level given with `log=<level>`, `None` if missing or not a valid level
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        self.get("log")?.parse().ok()
+    }
+}
+
+/// Split a command line into whitespace separated `key=value` and `flag` tokens.
+///
+/// A value can be put in double quotes to contain spaces, e.g. `msg="hi there"`.
+pub fn parse<'a>(raw: &'a str) -> CmdlineArgs<'a> {
+    let mut args = Vec::new();
+    let mut push = |token: &'a str| {
+        // there is no one to report to this early, just drop what does not fit
+        let _ = args.push(parse_token(token));
+    };
+
+    let mut start = None;
+    let mut in_quotes = false;
+    for (i, c) in raw.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if c.is_whitespace() && !in_quotes {
+            if let Some(start) = start.take() {
+                push(&raw[start..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        push(&raw[start..]);
+    }
+
+    CmdlineArgs { args }
+}
+
+fn parse_token(token: &str) -> Arg<'_> {
+    match token.split_once('=') {
+        Some((key, value)) => {
+            // an unterminated quote just runs to the end of the line
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"').unwrap_or(quoted),
+                None => value,
+            };
+            Arg {
+                key,
+                value: Some(value),
+            }
+        }
+        None => Arg {
+            key: token,
+            value: None,
+        },
+    }
+}
+
+#[test_case]
+fn test_parse_empty() {
+    let args = parse("   ");
+    assert!(args.args.is_empty());
+    assert_eq!(args.log_level(), None);
+}
+
+#[test_case]
+fn test_parse_values_and_flags() {
+    let args = parse("log=debug selftest  alloc=buddy");
+    assert_eq!(args.log_level(), Some(LevelFilter::Debug));
+    assert_eq!(args.get("alloc"), Some("buddy"));
+    assert!(args.flag("selftest"));
+    assert!(!args.flag("alloc"));
+    assert!(!args.flag("quiet"));
+}
+
+#[test_case]
+fn test_parse_last_key_wins() {
+    let args = parse("log=info log=warn");
+    assert_eq!(args.log_level(), Some(LevelFilter::Warn));
+}
+
+#[test_case]
+fn test_parse_quoted_value() {
+    let args = parse("motd=\"hello  world\" quiet name=\"unterminated value");
+    assert_eq!(args.get("motd"), Some("hello  world"));
+    assert!(args.flag("quiet"));
+    assert_eq!(args.get("name"), Some("unterminated value"));
+}
+
+#[test_case]
+fn test_parse_invalid_log_level() {
+    assert_eq!(parse("log=loud").log_level(), None);
+}
@@ -0,0 +1,198 @@
+use core::arch::x86_64::__cpuid;
+use heapless::String;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// CPUID leaves
+const LEAF_VENDOR: u32 = 0x0;
+const LEAF_FEATURES: u32 = 0x1;
+const LEAF_MAX_EXTENDED: u32 = 0x8000_0000;
+const LEAF_EXTENDED_FEATURES: u32 = 0x8000_0001;
+const LEAF_BRAND_FIRST: u32 = 0x8000_0002;
+const LEAF_BRAND_LAST: u32 = 0x8000_0004;
+
+/// CPU features that can be checked with `has_feature`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFeature {
+    Fpu,
+    Tsc,
+    Msr,
+    Apic,
+    Sse,
+    Sse2,
+    X2Apic,
+    Rdrand,
+    /// The no-execute page flag
+    Nx,
+}
+
+/// Register of a CPUID leaf a feature is reported in
+enum FeatureRegister {
+    Ecx,
+    Edx,
+}
+
+impl CpuFeature {
+    fn location(self) -> (u32, FeatureRegister, u32) {
+        match self {
+            CpuFeature::Fpu => (LEAF_FEATURES, FeatureRegister::Edx, 0),
+            CpuFeature::Tsc => (LEAF_FEATURES, FeatureRegister::Edx, 4),
+            CpuFeature::Msr => (LEAF_FEATURES, FeatureRegister::Edx, 5),
+            CpuFeature::Apic => (LEAF_FEATURES, FeatureRegister::Edx, 9),
+            CpuFeature::Sse => (LEAF_FEATURES, FeatureRegister::Edx, 25),
+            CpuFeature::Sse2 => (LEAF_FEATURES, FeatureRegister::Edx, 26),
+            CpuFeature::X2Apic => (LEAF_FEATURES, FeatureRegister::Ecx, 21),
+            CpuFeature::Rdrand => (LEAF_FEATURES, FeatureRegister::Ecx, 30),
+            CpuFeature::Nx => (LEAF_EXTENDED_FEATURES, FeatureRegister::Edx, 20),
+        }
+    }
+}
+
+/// Returns the 12 byte vendor id, e.g. `GenuineIntel` or `AuthenticAMD`
+pub fn vendor_string() -> [u8; 12] {
+    let result = unsafe { __cpuid(LEAF_VENDOR) };
+    let mut vendor = [0; 12];
+    // the vendor is stored in ebx, edx, ecx (in that order)
+    vendor[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&result.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&result.ecx.to_le_bytes());
+    vendor
+}
+
+/// Returns whether the CPU supports `feature`
+pub fn has_feature(feature: CpuFeature) -> bool {
+    let (leaf, register, bit) = feature.location();
+    // leaves above the maximum return garbage instead of zeros
+    let max_leaf = if leaf >= LEAF_MAX_EXTENDED {
+        unsafe { __cpuid(LEAF_MAX_EXTENDED) }.eax
+    } else {
+        unsafe { __cpuid(LEAF_VENDOR) }.eax
+    };
+    if leaf > max_leaf {
+        return false;
+    }
+    let result = unsafe { __cpuid(leaf) };
+    let value = match register {
+        FeatureRegister::Ecx => result.ecx,
+        FeatureRegister::Edx => result.edx,
+    };
+    value & (1 << bit) != 0
+}
+
+/// Returns the processor brand string, empty if the CPU does not report one
+pub fn brand_string() -> String<48> {
+    let mut brand = String::new();
+    let max_extended = unsafe { __cpuid(LEAF_MAX_EXTENDED) }.eax;
+    if max_extended < LEAF_BRAND_LAST {
+        return brand;
+    }
+
+    let mut bytes = [0u8; 48];
+    for (i, leaf) in (LEAF_BRAND_FIRST..=LEAF_BRAND_LAST).enumerate() {
+        let result = unsafe { __cpuid(leaf) };
+        for (j, register) in [result.eax, result.ebx, result.ecx, result.edx]
+            .iter()
+            .enumerate()
+        {
+            let offset = i * 16 + j * 4;
+            bytes[offset..offset + 4].copy_from_slice(&register.to_le_bytes());
+        }
+    }
+
+    // the string is nul padded and often has leading spaces
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    if let Ok(text) = core::str::from_utf8(&bytes[..length]) {
+        // it always fits, the capacity is the size of the raw bytes
+        let _ = brand.push_str(text.trim());
+    }
+    brand
+}
+
+/// Let SSE and x87 instructions run instead of raising #UD or #NM.
+///
+/// Clears CR0.EM and sets CR0.MP, and tells the CPU that the kernel saves the SSE
+/// state with `fxsave` (CR4.OSFXSR) and handles #XM (CR4.OSXMMEXCPT). Returns false
+/// and changes nothing if CPUID does not report SSE and SSE2.
+///
+/// The target still compiles floats to software routines, so this is for code that
+/// uses SSE instructions explicitly, like `asm!` or intrinsics.
+pub fn enable_sse() -> bool {
+    if !has_feature(CpuFeature::Sse) || !has_feature(CpuFeature::Sse2) {
+        return false;
+    }
+    unsafe {
+        let mut cr0 = Cr0::read();
+        cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
+        cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        Cr0::write(cr0);
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    true
+}
+
+/// Whether `enable_sse` has turned SSE on
+pub fn sse_enabled() -> bool {
+    Cr4::read().contains(Cr4Flags::OSFXSR) && !Cr0::read().contains(Cr0Flags::EMULATE_COPROCESSOR)
+}
+
+/// Log the vendor, brand and supported features of the CPU
+pub fn print_cpu_info() {
+    let vendor = vendor_string();
+    log::info!(
+        "CPU: {} ({})",
+        core::str::from_utf8(&vendor).unwrap_or("unknown"),
+        brand_string()
+    );
+    for feature in [
+        CpuFeature::Fpu,
+        CpuFeature::Tsc,
+        CpuFeature::Msr,
+        CpuFeature::Apic,
+        CpuFeature::Sse,
+        CpuFeature::Sse2,
+        CpuFeature::X2Apic,
+        CpuFeature::Rdrand,
+        CpuFeature::Nx,
+    ] {
+        log::info!("CPU feature {:?}: {}", feature, has_feature(feature));
+    }
+}
+
+#[test_case]
+fn test_vendor_string_known() {
+    let vendor = vendor_string();
+    assert!(vendor.iter().any(|&b| b != 0));
+    // QEMU reports the host vendor with KVM, or its own one with TCG
+    assert!(
+        [b"GenuineIntel", b"AuthenticAMD", b"TCGTCGTCGTCG"].contains(&&vendor),
+        "unknown vendor {:?}",
+        core::str::from_utf8(&vendor)
+    );
+}
+
+#[test_case]
+fn test_f64_math() {
+    let x: f64 = core::hint::black_box(1.5);
+    assert_eq!(x * 4.0 + 0.25, 6.25);
+    let error = x / 3.0 - 0.5;
+    assert!(-f64::EPSILON < error && error < f64::EPSILON);
+}
+
+#[test_case]
+fn test_sse_instructions_run() {
+    use core::arch::asm;
+
+    // `init` enabled SSE, otherwise `addsd` raises #UD
+    assert!(sse_enabled());
+    // xmm0 can not be named as an operand because the target disables sse,
+    // clobbering it is fine since the compiler never uses the SSE registers
+    let mut bits = 1.25f64.to_bits();
+    unsafe {
+        asm!(
+            "movq xmm0, {bits}",
+            "addsd xmm0, xmm0",
+            "movq {bits}, xmm0",
+            bits = inout(reg) bits,
+        );
+    }
+    assert_eq!(f64::from_bits(bits), 2.5);
+}
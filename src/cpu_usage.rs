@@ -0,0 +1,68 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Timer ticks the usage is averaged over
+pub const WINDOW_TICKS: u64 = 64;
+
+// set while the CPU is halted waiting for work
+static IDLE: AtomicBool = AtomicBool::new(false);
+// one bit per timer tick, set if the CPU was busy when it fired, the latest tick is bit 0
+static BUSY_HISTORY: AtomicU64 = AtomicU64::new(0);
+// ticks recorded so far, the window is not full right after boot
+static RECORDED_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Mark whether the CPU is about to halt until the next interrupt or has woken up.
+///
+/// `true` must only be stored right before a `hlt`, every tick that fires in between counts as idle.
+pub(crate) fn set_idle(idle: bool) {
+    IDLE.store(idle, Ordering::Relaxed);
+}
+
+/// Halt until the next interrupt, the time counts as idle
+pub fn idle_hlt() {
+    set_idle(true);
+    x86_64::instructions::hlt();
+    set_idle(false);
+}
+
+/// Called by the timer interrupt handler on every tick
+pub(crate) fn record_tick() {
+    let busy = !IDLE.load(Ordering::Relaxed);
+    // only the timer handler writes it, so there is no lost update
+    let history = BUSY_HISTORY.load(Ordering::Relaxed);
+    BUSY_HISTORY.store(history << 1 | u64::from(busy), Ordering::Relaxed);
+    RECORDED_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Rough percentage of the last `WINDOW_TICKS` timer ticks the CPU was not halted.
+///
+/// Only samples the state at each tick, so it is approximate, especially under QEMU.
+pub fn cpu_usage_percent() -> u8 {
+    let ticks = RECORDED_TICKS.load(Ordering::Relaxed).min(WINDOW_TICKS);
+    if ticks == 0 {
+        return 0;
+    }
+    let mask = u64::MAX >> (64 - ticks);
+    let busy = u64::from((BUSY_HISTORY.load(Ordering::Relaxed) & mask).count_ones());
+    (busy * 100 / ticks) as u8
+}
+
+#[test_case]
+fn test_busy_loop_is_full_usage() {
+    // spin through a whole window without halting
+    let end = crate::interrupts::ticks() + WINDOW_TICKS + 1;
+    while crate::interrupts::ticks() < end {
+        core::hint::spin_loop();
+    }
+    let usage = cpu_usage_percent();
+    assert!(usage >= 95, "usage is {}%", usage);
+}
+
+#[test_case]
+fn test_halting_is_idle() {
+    let end = crate::interrupts::ticks() + WINDOW_TICKS + 1;
+    while crate::interrupts::ticks() < end {
+        idle_hlt();
+    }
+    let usage = cpu_usage_percent();
+    assert!(usage <= 5, "usage is {}%", usage);
+}
@@ -0,0 +1,107 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// No file with that path exists
+    NotFound,
+    /// The path is empty or contains a `/`, there are no directories yet
+    InvalidPath,
+}
+
+/// A flat filesystem that keeps every file on the heap.
+///
+/// Must be created after `allocator::init_heap`.
+pub struct RamFs {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl RamFs {
+    pub const fn new() -> Self {
+        RamFs {
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Create the file at `path` with a copy of `data`, replacing the content if it exists
+    pub fn create(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        if path.is_empty() || path.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        match self.files.get_mut(path) {
+            Some(content) => {
+                content.clear();
+                content.extend_from_slice(data);
+            }
+            None => {
+                self.files.insert(String::from(path), Vec::from(data));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(Vec::as_slice)
+    }
+
+    /// Paths of all files, sorted
+    pub fn list(&self) -> Vec<&str> {
+        self.files.keys().map(String::as_str).collect()
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        match self.files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(FsError::NotFound),
+        }
+    }
+}
+
+impl Default for RamFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_create_and_read() {
+    let mut fs = RamFs::new();
+    fs.create("hello.txt", b"hello world").unwrap();
+    assert_eq!(fs.read("hello.txt"), Some(&b"hello world"[..]));
+    assert_eq!(fs.read("missing.txt"), None);
+}
+
+#[test_case]
+fn test_create_overwrites() {
+    let mut fs = RamFs::new();
+    fs.create("file", b"a longer first version").unwrap();
+    fs.create("file", b"short").unwrap();
+    assert_eq!(fs.read("file"), Some(&b"short"[..]));
+    assert_eq!(fs.list().len(), 1);
+}
+
+#[test_case]
+fn test_create_rejects_directories() {
+    let mut fs = RamFs::new();
+    assert_eq!(fs.create("", b""), Err(FsError::InvalidPath));
+    assert_eq!(fs.create("dir/file", b""), Err(FsError::InvalidPath));
+}
+
+#[test_case]
+fn test_list_is_sorted() {
+    let mut fs = RamFs::new();
+    fs.create("b", b"").unwrap();
+    fs.create("c", b"").unwrap();
+    fs.create("a", b"").unwrap();
+    assert_eq!(fs.list(), ["a", "b", "c"]);
+}
+
+#[test_case]
+fn test_delete() {
+    let mut fs = RamFs::new();
+    fs.create("file", b"data").unwrap();
+    assert_eq!(fs.delete("file"), Ok(()));
+    assert_eq!(fs.read("file"), None);
+    assert_eq!(fs.delete("file"), Err(FsError::NotFound));
+}
@@ -5,20 +5,35 @@ use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
+/// Index of the interrupt stack table entry used by the double fault handler,
+/// so that a kernel stack overflow does not end in a triple fault
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Index of the interrupt stack table entry used by the page fault handler,
+/// a stack overflow hits the guard page below the stack and needs a valid stack to be handled
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+/// Index of the interrupt stack table entry used by the general protection fault handler
+pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 2;
+
+const STACK_SIZE: usize = 4096 * 5;
+
+// the end of a new static stack, stacks grow downwards
+macro_rules! ist_stack {
+    () => {{
+        // `mut` is used to prevent the bootloader from mapping it to read-only page
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
+    }};
+}
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            // `mut` is used to prevent the bootloader from mapping it to read-only page
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = ist_stack!();
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = ist_stack!();
+        tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] = ist_stack!();
         tss
     };
 }
@@ -37,6 +52,7 @@ lazy_static! {
     };
 }
 
+/// Load the GDT and the TSS, must be called before the IDT is loaded
 pub fn init() {
     GDT.0.load();
 
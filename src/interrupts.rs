@@ -1,11 +1,15 @@
-use crate::{gdt, hlt_loop, print, println};
+use crate::{apic, gdt, hlt_loop, print, println, serial, task, try_println, watchdog};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
-use spin::Mutex;
 use x86_64::instructions::port::Port;
 use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+pub mod trampoline;
+
+use trampoline::{exception_trampoline, ExceptionFrame, GeneralRegisters};
 
 // Exceptions Handling
 
@@ -14,15 +18,31 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, Pag
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(break_point_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        // these go through a trampoline that saves the general purpose registers
         unsafe {
+            idt.breakpoint
+                .set_handler_addr(VirtAddr::from_ptr(break_point_trampoline as *const ()));
             idt.double_fault
-                .set_handler_fn(double_fault_handler)
+                .set_handler_addr(VirtAddr::from_ptr(double_fault_trampoline as *const ()))
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        // on their own stacks as well, they are what a kernel stack overflow causes first
+        unsafe {
+            idt.general_protection_fault
+                .set_handler_fn(general_protection_fault_handler)
+                .set_stack_index(gdt::GENERAL_PROTECTION_FAULT_IST_INDEX);
+            idt.page_fault
+                .set_handler_addr(VirtAddr::from_ptr(page_fault_trampoline as *const ()))
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
+        idt.simd_floating_point
+            .set_handler_fn(simd_floating_point_handler);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Com1.as_usize()].set_handler_fn(serial_interrupt_handler);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -31,10 +51,111 @@ pub fn init_idt() {
     IDT.load();
 }
 
+// - statistics
+
+// vectors of the CPU exceptions that have a handler
+const DIVIDE_ERROR_VECTOR: u8 = 0;
+const BREAKPOINT_VECTOR: u8 = 3;
+const INVALID_OPCODE_VECTOR: u8 = 6;
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+const GENERAL_PROTECTION_FAULT_VECTOR: u8 = 13;
+const PAGE_FAULT_VECTOR: u8 = 14;
+const SIMD_FLOATING_POINT_VECTOR: u8 = 19;
+
+// how often each vector has fired since boot
+static INTERRUPT_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how often each interrupt vector has fired since boot
+pub fn interrupt_counts() -> [u64; 256] {
+    let mut counts = [0; 256];
+    for (count, counter) in counts.iter_mut().zip(INTERRUPT_COUNTS.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+    counts
+}
+
+// name of the vectors that have a handler
+fn vector_name(vector: u8) -> Option<&'static str> {
+    let name = match vector {
+        DIVIDE_ERROR_VECTOR => "divide error",
+        BREAKPOINT_VECTOR => "breakpoint",
+        INVALID_OPCODE_VECTOR => "invalid opcode",
+        DOUBLE_FAULT_VECTOR => "double fault",
+        GENERAL_PROTECTION_FAULT_VECTOR => "general protection fault",
+        PAGE_FAULT_VECTOR => "page fault",
+        SIMD_FLOATING_POINT_VECTOR => "simd floating point",
+        apic::SPURIOUS_VECTOR => "spurious",
+        v if v == InterruptIndex::Timer.as_u8() => "timer",
+        v if v == InterruptIndex::Keyboard.as_u8() => "keyboard",
+        v if v == InterruptIndex::Com1.as_u8() => "com1",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Print every vector that has fired at least once with its count
+pub fn print_interrupt_stats() {
+    for (vector, count) in interrupt_counts().iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        let vector = vector as u8;
+        println!(
+            "{:>3} {:<24} {}",
+            vector,
+            vector_name(vector).unwrap_or("unknown"),
+            count
+        );
+    }
+}
+
 // - handler functions
 
-extern "x86-interrupt" fn break_point_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(DIVIDE_ERROR_VECTOR);
+    try_println!("EXCEPTION: DIVIDE BY ZERO\n{:#?}", stack_frame);
+    // #DE is a fault, returning would run the faulting `div` again
+    hlt_loop();
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(SIMD_FLOATING_POINT_VECTOR);
+    let mut mxcsr: u32 = 0;
+    unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr, options(nostack)) };
+    // the low 6 bits of MXCSR flag which exception happened
+    try_println!(
+        "EXCEPTION: SIMD FLOATING POINT\nMXCSR: {:#x}\n{:#?}",
+        mxcsr,
+        stack_frame
+    );
+    hlt_loop();
+}
+
+// registers at the last breakpoint, for debugging and the tests
+static BREAKPOINT_REGISTERS: spin::Mutex<Option<GeneralRegisters>> = spin::Mutex::new(None);
+
+/// The general purpose registers when the last breakpoint exception happened
+pub fn last_breakpoint_registers() -> Option<GeneralRegisters> {
+    *BREAKPOINT_REGISTERS.lock()
+}
+
+exception_trampoline!(break_point_trampoline, break_point_handler);
+
+extern "C" fn break_point_handler(frame: &ExceptionFrame) {
+    count_interrupt(BREAKPOINT_VECTOR);
+    // only skipped if the breakpoint hit while the registers were being read
+    if let Some(mut registers) = BREAKPOINT_REGISTERS.try_lock() {
+        *registers = Some(frame.registers);
+    }
+    try_println!(
+        "EXCEPTION: BREAKPOINT\n{:#?}\n{}",
+        frame.stack_frame,
+        frame.registers
+    );
 }
 
 #[test_case]
@@ -43,22 +164,138 @@ fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+#[test_case]
+fn test_breakpoint_is_counted() {
+    let before = interrupt_counts()[usize::from(BREAKPOINT_VECTOR)];
+    for _ in 0..3 {
+        x86_64::instructions::interrupts::int3();
+    }
+    let after = interrupt_counts()[usize::from(BREAKPOINT_VECTOR)];
+    assert_eq!(after - before, 3);
+}
+
+#[test_case]
+fn test_breakpoint_saves_registers() {
+    use alloc::format;
+
+    unsafe {
+        core::arch::asm!(
+            "int3",
+            in("rax") 0xaaaa_u64,
+            in("r12") 0x1212_1212_u64,
+            in("r15") 0x1515_1515_1515_1515_u64,
+        );
+    }
+    let registers = last_breakpoint_registers().expect("no breakpoint registers saved");
+    assert_eq!(registers.rax, 0xaaaa);
+    assert_eq!(registers.r12, 0x1212_1212);
+    assert_eq!(registers.r15, 0x1515_1515_1515_1515);
+
+    let dump = format!("{}", registers);
+    assert_eq!(dump.lines().count(), 5);
+    assert!(dump.starts_with("rax=0x000000000000aaaa rbx="));
+    assert!(dump.contains("r12=0x0000000012121212"));
 }
 
-extern "x86-interrupt" fn page_fault_handler(
+exception_trampoline!(double_fault_trampoline, double_fault_handler, error_code);
+
+extern "C" fn double_fault_handler(frame: &ExceptionFrame) -> ! {
+    count_interrupt(DOUBLE_FAULT_VECTOR);
+    panic!(
+        "EXCEPTION: DOUBLE FAULT\n{:#?}\n{}",
+        frame.stack_frame, frame.registers
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(INVALID_OPCODE_VECTOR);
+    try_println!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    hlt_loop();
+}
+
+/// Descriptor table a selector error code refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// Error code pushed by exceptions caused by a segment selector, like #GP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode(pub u64);
+
+impl SelectorErrorCode {
+    /// The exception happened while delivering an external interrupt
+    pub fn external(self) -> bool {
+        self.0 & 0b1 != 0
+    }
+
+    pub fn table(self) -> DescriptorTable {
+        // bit 1 means IDT regardless of bit 2
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    /// Index of the descriptor entry in the table
+    pub fn index(self) -> u64 {
+        (self.0 >> 3) & 0x1FFF
+    }
+}
+
+#[test_case]
+fn test_selector_error_code() {
+    let code = SelectorErrorCode(0x1230);
+    assert!(!code.external());
+    assert_eq!(code.table(), DescriptorTable::Gdt);
+    assert_eq!(code.index(), 0x246);
+    assert_eq!(SelectorErrorCode(0b110).table(), DescriptorTable::Idt);
+    assert_eq!(SelectorErrorCode(0b101).table(), DescriptorTable::Ldt);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
+    error_code: u64,
 ) {
-    println!("EXCEPTION: PAGE FAULT");
+    count_interrupt(GENERAL_PROTECTION_FAULT_VECTOR);
+    try_println!("EXCEPTION: GENERAL PROTECTION FAULT");
+    // the error code is 0 if the fault is not caused by a segment selector
+    let selector = SelectorErrorCode(error_code);
+    try_println!(
+        "Error Code: {:#x} (External: {}, Table: {:?}, Index: {})",
+        error_code,
+        selector.external(),
+        selector.table(),
+        selector.index()
+    );
+    try_println!("{:#?}", stack_frame);
+    hlt_loop();
+}
+
+exception_trampoline!(page_fault_trampoline, page_fault_handler, error_code);
+
+extern "C" fn page_fault_handler(frame: &ExceptionFrame) {
+    count_interrupt(PAGE_FAULT_VECTOR);
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    let stack_frame = &frame.stack_frame;
+    try_println!("EXCEPTION: PAGE FAULT");
     // CR2 register is automatically set by the CPU on a page fault
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
+    try_println!("Accessed Address: {:?}", Cr2::read());
+    try_println!("Error Code: {:?}", error_code);
+    // the error code has no PROTECTION_VIOLATION bit when the page was not present
+    try_println!(
+        "Present: {}, Write: {}, User: {}, Instruction Fetch: {}",
+        error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION),
+        error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE),
+        error_code.contains(PageFaultErrorCode::USER_MODE),
+        error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+    );
+    try_println!("Instruction Pointer: {:?}", stack_frame.instruction_pointer);
+    try_println!("{:#?}", stack_frame);
+    try_println!("{}", frame.registers);
     hlt_loop();
 }
 
@@ -76,10 +313,12 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    // IRQ 4, the first serial interface
+    Com1 = PIC_1_OFFSET + 4,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -88,41 +327,141 @@ impl InterruptIndex {
     }
 }
 
+/// Clear the PIC mask bit of the interrupt, the firmware may leave lines masked
+pub(crate) fn unmask_pic_interrupt(index: InterruptIndex) {
+    let irq = index.as_u8() - PIC_1_OFFSET;
+    let mut pics = PICS.lock();
+    unsafe {
+        let [master, slave] = pics.read_masks();
+        if irq < 8 {
+            pics.write_masks(master & !(1 << irq), slave);
+        } else {
+            pics.write_masks(master, slave & !(1 << (irq - 8)));
+        }
+    }
+}
+
+// hardware interrupt handlers that are currently running, more than one when they nest
+static HANDLER_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the current code runs in the handler of a hardware interrupt
+pub fn in_interrupt_handler() -> bool {
+    HANDLER_DEPTH.load(Ordering::Relaxed) > 0
+}
+
+/// Counts as running in a hardware interrupt handler until dropped
+struct HandlerContext;
+
+impl HandlerContext {
+    fn enter() -> Self {
+        HANDLER_DEPTH.fetch_add(1, Ordering::Relaxed);
+        HandlerContext
+    }
+}
+
+impl Drop for HandlerContext {
+    fn drop(&mut self) {
+        HANDLER_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// tell whichever interrupt controller is in use that the handler is done
+fn end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) };
+    }
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(apic::SPURIOUS_VECTOR);
+    // spurious interrupts of the local APIC must not be acknowledged
+}
+
+// number of timer interrupts since `init`
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of timer interrupts that have fired so far
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = HandlerContext::enter();
+    count_interrupt(InterruptIndex::Timer.as_u8());
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    crate::cpu_usage::record_tick();
+    task::timer::wake_tick_waiter();
+    crate::check_test_timeout();
+    watchdog::check();
+    crate::stack_canary::check_stack_canary();
     print!(".");
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    end_of_interrupt(InterruptIndex::Timer);
+}
+
+#[test_case]
+fn test_not_in_interrupt_handler() {
+    assert!(!in_interrupt_handler());
+}
+
+#[test_case]
+fn test_timer_ticks_advance() {
+    let start = ticks();
+    // interrupts are enabled by `init`, so the next timer interrupt wakes us up
+    while ticks() == start {
+        x86_64::instructions::hlt();
     }
 }
 
+// not a valid `char`, marks that no key is pending
+const NO_KEY: u32 = u32::MAX;
+
+// the last character typed on the keyboard that is not consumed yet
+static LAST_KEY: AtomicU32 = AtomicU32::new(NO_KEY);
+
+/// Takes the last character typed on the keyboard, if any.
+///
+/// Each key press is returned only once, and only the most recent one is kept.
+pub fn last_key() -> Option<char> {
+    char::from_u32(LAST_KEY.swap(NO_KEY, Ordering::Relaxed))
+}
+
+/// Remember `character` as the last typed key, called once a scancode is decoded
+pub(crate) fn record_key(character: char) {
+    LAST_KEY.store(u32::from(character), Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_last_key_is_consumed() {
+    record_key('a');
+    assert_eq!(last_key(), Some('a'));
+    assert_eq!(last_key(), None);
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
-    }
-
-    let mut keyboard = KEYBOARD.lock();
-    // I/O port of PS/2 controller
-    let mut port = Port::new(0x60);
-
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
-    }
+    let _context = HandlerContext::enter();
+    count_interrupt(InterruptIndex::Keyboard.as_u8());
+    // I/O ports of PS/2 controller
+    let mut status_port: Port<u8> = Port::new(0x64);
+    let mut data_port = Port::new(0x60);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    // bit 0 of the status register is set when there is a byte to read,
+    // otherwise there is nothing to decode but the interrupt still needs an EOI
+    let output_buffer_full = unsafe { status_port.read() } & 1 != 0;
+    if output_buffer_full {
+        // decoding happens in `task::keyboard`, keep the handler short
+        let scancode: u8 = unsafe { data_port.read() };
+        task::keyboard::add_scancode(scancode);
     }
+
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = HandlerContext::enter();
+    count_interrupt(InterruptIndex::Com1.as_u8());
+    serial::drain_receive_fifo();
+    end_of_interrupt(InterruptIndex::Com1);
 }
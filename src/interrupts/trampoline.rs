@@ -0,0 +1,134 @@
+use core::fmt;
+use x86_64::structures::idt::InterruptStackFrameValue;
+
+/// The general purpose registers at the time of an exception, in the order the
+/// trampoline pushes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct GeneralRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+impl fmt::Display for GeneralRegisters {
+    /// Three registers per line, like `rax=0x0000000000000000`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = [
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rbp", self.rbp),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+        ];
+        for line in registers.chunks(3) {
+            for (i, (name, value)) in line.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{:<3}={:#018x}", name, value)?;
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything on the stack when a trampoline calls its handler
+#[derive(Debug)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub registers: GeneralRegisters,
+    /// The error code of the exception, 0 for exceptions that have none
+    pub error_code: u64,
+    pub stack_frame: InterruptStackFrameValue,
+}
+
+/// Define a naked interrupt entry `$name` that saves all general purpose registers
+/// and calls `$handler` with a `&ExceptionFrame`.
+///
+/// `$handler` must be an `extern "C" fn(&ExceptionFrame)`, the signature is not
+/// checked. Add `error_code` for exceptions where the CPU pushes one, the other
+/// trampolines push a 0 instead so both have the same layout. Install the entry
+/// with `set_handler_addr`.
+macro_rules! exception_trampoline {
+    ($name:ident, $handler:path) => {
+        exception_trampoline!(@define $name, $handler, ["push 0"]);
+    };
+    ($name:ident, $handler:path, error_code) => {
+        exception_trampoline!(@define $name, $handler, []);
+    };
+    (@define $name:ident, $handler:path, [$($error_code:literal)?]) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                $($error_code,)?
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                // the CPU aligned the stack before pushing the 5 word stack frame, with the
+                // error code and the registers 21 words are pushed, so one more aligns it again
+                "sub rsp, 8",
+                "cld",
+                "call {handler}",
+                "add rsp, 8",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                // drop the error code
+                "add rsp, 8",
+                "iretq",
+                handler = sym $handler,
+            );
+        }
+    };
+}
+
+pub(crate) use exception_trampoline;
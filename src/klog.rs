@@ -0,0 +1,132 @@
+use crate::sync::Mutex;
+use crate::{serial_print, vga_buffer};
+use core::fmt;
+use core::fmt::Write;
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+/// Bytes of screen output the kernel log keeps
+pub const KLOG_SIZE: usize = 4096;
+
+/// A ring of the most recent output, the oldest lines are dropped when it is full
+pub struct LogRing<const N: usize> {
+    buf: [u8; N],
+    // index of the oldest byte
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        LogRing {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.drop_oldest_line();
+        }
+        self.buf[(self.start + self.len) % N] = byte;
+        self.len += 1;
+    }
+
+    // drop whole lines so the ring never starts in the middle of one
+    fn drop_oldest_line(&mut self) {
+        while self.len > 0 {
+            let byte = self.buf[self.start];
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+
+    /// Copy the content, oldest first, into `out` and return the number of bytes copied.
+    pub fn read(&self, out: &mut [u8]) -> usize {
+        let len = self.len.min(out.len());
+        for (i, byte) in out[..len].iter_mut().enumerate() {
+            *byte = self.buf[(self.start + i) % N];
+        }
+        len
+    }
+}
+
+impl<const N: usize> fmt::Write for LogRing<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref KLOG: Mutex<LogRing<KLOG_SIZE>> = Mutex::new(LogRing::new());
+}
+
+/// Append formatted output to the kernel log, called by `print!`.
+///
+/// Does not allocate, a full ring just drops its oldest lines.
+pub(crate) fn record(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let _ = KLOG.lock().write_fmt(args);
+    });
+}
+
+/// Like `record`, but skips the output if the log is locked, for `try_print!`
+pub(crate) fn try_record(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        if let Some(mut klog) = KLOG.try_lock() {
+            let _ = klog.write_fmt(args);
+        }
+    });
+}
+
+/// Copy the kernel log, oldest first, into `out` and return the number of bytes copied.
+pub fn read_klog(out: &mut [u8]) -> usize {
+    interrupts::without_interrupts(|| KLOG.lock().read(out))
+}
+
+/// Print the whole kernel log to the screen and serial again.
+///
+/// The output itself does not go into the log.
+pub fn dump_klog() {
+    let mut buf = [0; KLOG_SIZE];
+    let len = read_klog(&mut buf);
+    // only whole lines are dropped from the ring, so this only fails if a write was torn
+    let text = match core::str::from_utf8(&buf[..len]) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8(&buf[..error.valid_up_to()]).unwrap(),
+    };
+    vga_buffer::print_uncaptured(text);
+    serial_print!("{}", text);
+}
+
+#[test_case]
+fn test_log_ring_drops_oldest_lines() {
+    let mut ring: LogRing<16> = LogRing::new();
+    write!(ring, "one\ntwo\nthree\n").unwrap();
+    write!(ring, "four\n").unwrap();
+
+    let mut out = [0; 16];
+    let len = ring.read(&mut out);
+    // "one\n" had to go to make room for "four\n"
+    assert_eq!(&out[..len], b"two\nthree\nfour\n");
+}
+
+#[test_case]
+fn test_print_is_recorded() {
+    crate::println!("klog test line 1");
+    crate::println!("klog test line 2");
+
+    let mut buf = [0; KLOG_SIZE];
+    let len = read_klog(&mut buf);
+    let text = core::str::from_utf8(&buf[..len]).unwrap();
+    let first = text.rfind("klog test line 1").expect("line 1 missing");
+    let second = text.rfind("klog test line 2").expect("line 2 missing");
+    assert!(first < second);
+}
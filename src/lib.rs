@@ -9,19 +9,47 @@
 #[cfg(test)]
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use x86_64::instructions::port::Port;
+#[cfg(test)]
+use x86_64::VirtAddr;
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod backtrace;
+pub mod bench;
+pub mod cmdline;
+pub mod cpu;
+pub mod cpu_usage;
+pub mod fs;
 pub mod gdt;
 pub mod interrupts;
+pub mod klog;
+pub mod logger;
 pub mod memory;
+pub mod msr;
+pub mod pci;
+pub mod rand;
+pub mod rtc;
 pub mod serial;
+pub mod shell;
+pub mod smp;
+pub mod stack_canary;
+pub mod sync;
+pub mod task;
+pub mod time;
+pub mod util;
 pub mod vga_buffer;
+pub mod watchdog;
 
 pub trait Testable {
     fn run(&self) -> ();
+    /// The name the test is reported and filtered by
+    fn name(&self) -> &'static str;
 }
 
 impl<T> Testable for T
@@ -29,10 +57,109 @@ where
     T: Fn(),
 {
     fn run(&self) -> () {
-        serial_print!("{}...\t", core::any::type_name::<T>());
+        let name = self.name();
+        serial_print!("{}...\t", name);
+        arm_test_timeout(name);
         self();
+        disarm_test_timeout();
         serial_println!("[ok]");
     }
+
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// A test that only passes if it panics.
+///
+/// `#[test_case]` functions are plain `Fn()` values, so use the `should_panic_test!`
+/// macro to register a test wrapped in this type instead.
+pub struct ShouldPanic {
+    name: &'static str,
+    test: fn(),
+}
+
+impl ShouldPanic {
+    pub const fn new(name: &'static str, test: fn()) -> ShouldPanic {
+        ShouldPanic { name, test }
+    }
+}
+
+impl Testable for ShouldPanic {
+    fn run(&self) -> () {
+        serial_print!("{}...\t", self.name);
+        // the panic handler reports `[ok]` and carries on with the next test
+        EXPECT_PANIC.store(true, Ordering::SeqCst);
+        arm_test_timeout(self.name);
+        (self.test)();
+        disarm_test_timeout();
+        EXPECT_PANIC.store(false, Ordering::SeqCst);
+        serial_println!("[failed]\n");
+        serial_println!("Error: test did not panic\n");
+        fail_qemu();
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Register a `#[test_case]` that is expected to panic
+///
+/// ```ignore
+/// should_panic_test! {
+///     fn test_fails() {
+///         assert_eq!(0, 1);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! should_panic_test {
+    (fn $name:ident() $body:block) => {
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        static $name: $crate::ShouldPanic =
+            $crate::ShouldPanic::new(concat!(module_path!(), "::", stringify!($name)), {
+                fn $name() $body
+                $name
+            });
+    };
+}
+
+// set while a `ShouldPanic` test runs
+static EXPECT_PANIC: AtomicBool = AtomicBool::new(false);
+
+// the tests passed to `test_runner`, so the panic handler can run the ones that are left
+static TESTS: AtomicPtr<&'static dyn Testable> = AtomicPtr::new(ptr::null_mut());
+static TEST_COUNT: AtomicUsize = AtomicUsize::new(0);
+static NEXT_TEST: AtomicUsize = AtomicUsize::new(0);
+// only the tests whose name contains the filter run, see `set_test_filter`
+static TEST_FILTER: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static TEST_FILTER_LEN: AtomicUsize = AtomicUsize::new(0);
+static FILTERED_OUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Only run the tests whose name contains `filter`, must be called before `test_main`.
+///
+/// Without it the `test-filter` argument of the kernel command line is used.
+pub fn set_test_filter(filter: &'static str) {
+    TEST_FILTER.store(filter.as_ptr() as *mut u8, Ordering::SeqCst);
+    TEST_FILTER_LEN.store(filter.len(), Ordering::SeqCst);
+}
+
+fn test_filter() -> Option<&'static str> {
+    let filter = TEST_FILTER.load(Ordering::SeqCst);
+    if filter.is_null() {
+        return cmdline::parse(cmdline::BOOT_CMDLINE).get("test-filter");
+    }
+    unsafe {
+        let bytes = core::slice::from_raw_parts(filter, TEST_FILTER_LEN.load(Ordering::SeqCst));
+        // only ever set from a `&'static str`
+        Some(core::str::from_utf8_unchecked(bytes))
+    }
+}
+
+fn is_selected(test: &dyn Testable) -> bool {
+    test_filter().map_or(true, |filter| test.name().contains(filter))
 }
 
 // include this function only for tests
@@ -40,18 +167,110 @@ where
 // because the trick implementation of Testable, any type that can be called like a function (i.e., implements the Fn() trait) also automatically implements the Testable trait
 // It is a list of references to types that can be called like a function
 pub fn test_runner(tests: &[&dyn Testable]) {
-    serial_println!("Running {} tests", tests.len());
-    for test in tests {
-        test.run();
+    let selected = tests.iter().filter(|test| is_selected(**test)).count();
+    FILTERED_OUT.store(tests.len() - selected, Ordering::SeqCst);
+    serial_println!("Running {} tests", selected);
+    // the test functions are statics generated by the test framework
+    TESTS.store(
+        tests.as_ptr() as *mut &'static dyn Testable,
+        Ordering::SeqCst,
+    );
+    TEST_COUNT.store(tests.len(), Ordering::SeqCst);
+    NEXT_TEST.store(0, Ordering::SeqCst);
+    run_remaining_tests();
+}
+
+fn run_remaining_tests() -> ! {
+    let tests = TESTS.load(Ordering::SeqCst);
+    loop {
+        let index = NEXT_TEST.fetch_add(1, Ordering::SeqCst);
+        if index >= TEST_COUNT.load(Ordering::SeqCst) {
+            break;
+        }
+        let test = unsafe { *tests.add(index) };
+        if is_selected(test) {
+            test.run();
+        }
     }
-    exit_qemu(QemuExitCode::Success);
+    let filtered_out = FILTERED_OUT.load(Ordering::SeqCst);
+    if filtered_out > 0 {
+        serial_println!("filtered out {} tests", filtered_out);
+    }
+    success_qemu();
+}
+
+/// Seconds a single test may take
+const TEST_TIMEOUT_SECS: u64 = 20;
+
+// tick count at which the running test times out, 0 if no test is running
+static TEST_DEADLINE: AtomicU64 = AtomicU64::new(0);
+// name of the running test, for the timeout message
+static CURRENT_TEST_NAME: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static CURRENT_TEST_NAME_LEN: AtomicUsize = AtomicUsize::new(0);
+// exit code when a test times out, a test can expect a timeout by changing it
+static TIMEOUT_EXIT_CODE: AtomicU32 = AtomicU32::new(QemuExitCode::Failed as u32);
+
+fn arm_test_timeout(name: &'static str) {
+    // the timer handler must not see the new name with the old deadline
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        CURRENT_TEST_NAME.store(name.as_ptr() as *mut u8, Ordering::SeqCst);
+        CURRENT_TEST_NAME_LEN.store(name.len(), Ordering::SeqCst);
+        let timeout_ticks = TEST_TIMEOUT_SECS * u64::from(time::pit_frequency());
+        TEST_DEADLINE.store(interrupts::ticks() + timeout_ticks, Ordering::SeqCst);
+    });
+}
+
+fn disarm_test_timeout() {
+    TEST_DEADLINE.store(0, Ordering::SeqCst);
+}
+
+/// Exit QEMU with `exit_code` instead of `Failed` when a test times out
+pub fn set_timeout_exit_code(exit_code: QemuExitCode) {
+    TIMEOUT_EXIT_CODE.store(exit_code as u32, Ordering::SeqCst);
+}
+
+/// Called by the timer interrupt handler, ends the test run if the running test is stuck.
+///
+/// Only tests that keep interrupts enabled can be caught.
+pub(crate) fn check_test_timeout() {
+    let deadline = TEST_DEADLINE.load(Ordering::SeqCst);
+    if deadline == 0 || interrupts::ticks() < deadline {
+        return;
+    }
+    disarm_test_timeout();
+    let name = unsafe {
+        let bytes = core::slice::from_raw_parts(
+            CURRENT_TEST_NAME.load(Ordering::SeqCst),
+            CURRENT_TEST_NAME_LEN.load(Ordering::SeqCst),
+        );
+        // only ever set from a `&'static str`
+        core::str::from_utf8_unchecked(bytes)
+    };
+    serial_try_println!("[timeout]\n");
+    serial_try_println!(
+        "Error: {} did not finish within {} seconds\n",
+        name,
+        TEST_TIMEOUT_SECS
+    );
+    unsafe {
+        let mut port = Port::new(0xf4);
+        port.write(TIMEOUT_EXIT_CODE.load(Ordering::SeqCst));
+    }
+    hlt_loop();
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    disarm_test_timeout();
+    vga_buffer::restore_color_after_panic();
+    if EXPECT_PANIC.swap(false, Ordering::SeqCst) {
+        serial_println!("[ok]");
+        // there is no unwinding, so just continue on top of the panicked stack
+        run_remaining_tests();
+    }
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
-    exit_qemu(QemuExitCode::Failed);
-    hlt_loop();
+    backtrace::print_backtrace();
+    fail_qemu();
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -74,13 +293,95 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     }
 }
 
+/// Exit QEMU with `QemuExitCode::Success`, halts if there is no isa-debug-exit device
+pub fn success_qemu() -> ! {
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+/// Exit QEMU with `QemuExitCode::Failed`, halts if there is no isa-debug-exit device
+pub fn fail_qemu() -> ! {
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+// status and command register of the PS/2 controller
+const PS2_COMMAND_PORT: u16 = 0x64;
+// status bit that is set until the controller took the last byte written to it
+const PS2_INPUT_BUFFER_FULL: u8 = 1 << 1;
+// pulses the reset line of the CPU
+const PS2_PULSE_RESET: u8 = 0xFE;
+// status reads before giving up on the controller
+const PS2_WAIT_POLLS: usize = 100_000;
+
+/// Wait until the PS/2 controller accepts a command, `false` if it stays busy
+pub fn wait_ps2_ready() -> bool {
+    let mut status: Port<u8> = Port::new(PS2_COMMAND_PORT);
+    (0..PS2_WAIT_POLLS).any(|_| {
+        core::hint::spin_loop();
+        let status = unsafe { status.read() };
+        status & PS2_INPUT_BUFFER_FULL == 0
+    })
+}
+
+/// Everything `reboot` does before it sends the reset command.
+///
+/// Disables interrupts, so nothing runs in between, and returns whether the PS/2
+/// controller is ready for the command.
+pub fn prepare_reboot() -> bool {
+    x86_64::instructions::interrupts::disable();
+    wait_ps2_ready()
+}
+
+/// Reset the machine through the PS/2 controller.
+///
+/// If the controller is missing or ignores the command, a triple fault resets the CPU instead.
+pub fn reboot() -> ! {
+    if prepare_reboot() {
+        unsafe { Port::new(PS2_COMMAND_PORT).write(PS2_PULSE_RESET) };
+        // the reset does not happen instantly
+        wait_ps2_ready();
+    }
+    // with an empty IDT the breakpoint can not be delivered, that is a triple fault
+    let empty_idt = x86_64::structures::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::zero(),
+    };
+    unsafe { x86_64::instructions::tables::lidt(&empty_idt) };
+    x86_64::instructions::interrupts::int3();
+    hlt_loop();
+}
+
+#[test_case]
+fn test_qemu_exits_diverge() {
+    // only type checked, calling them would end the test run
+    fn _exit(passed: bool) -> u32 {
+        if passed {
+            success_qemu()
+        } else {
+            fail_qemu()
+        }
+    }
+    let _: fn() -> ! = success_qemu;
+    let _: fn() -> ! = fail_qemu;
+    let _: fn() -> ! = reboot;
+}
+
 #[cfg(test)]
 entry_point!(test_kernel_main);
 
 /// Entry point for `cargo test`
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();
+    stack_canary::init(boot_info);
+    // some of the unit tests need the heap
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    vga_buffer::remap(&mut mapper, &mut frame_allocator).expect("remapping the VGA buffer failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
     test_main();
     hlt_loop();
 }
@@ -91,15 +392,27 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
+/// Idle the CPU forever, never returns.
+///
+/// `hlt` sleeps until the next interrupt instead of busy spinning,
+/// interrupt handlers still run in between.
+#[inline]
 pub fn hlt_loop() -> ! {
     loop {
-        x86_64::instructions::hlt();
+        cpu_usage::idle_hlt();
     }
 }
 
 pub fn init() {
     gdt::init();
+    memory::enable_no_execute();
+    cpu::enable_sse();
+    vga_buffer::enable_cursor();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::unmask_pic_interrupt(interrupts::InterruptIndex::Com1);
+    time::set_pit_frequency(time::DEFAULT_PIT_HZ);
     x86_64::instructions::interrupts::enable();
+    logger::init_logger(log::LevelFilter::Info);
+    cpu::print_cpu_info();
 }
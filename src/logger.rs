@@ -0,0 +1,117 @@
+use crate::vga_buffer::Color;
+use crate::{println, serial, serial_println, vga_buffer, with_color};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// A `log` implementation that writes every record to serial and the VGA screen.
+///
+/// Each of them has its own minimum level, see `serial::set_min_level` and
+/// `vga_buffer::set_min_level`.
+pub struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level();
+        if serial::min_level() >= level {
+            serial_println!("[{}] {}", level, record.args());
+        }
+        if vga_buffer::min_level() < level {
+            return;
+        }
+        // make warnings and errors stand out on the screen
+        let color = match level {
+            Level::Error => Some(Color::Red),
+            Level::Warn => Some(Color::LightRed),
+            _ => None,
+        };
+        match color {
+            Some(color) => with_color!(color, Color::Black, "[{}] {}\n", level, record.args()),
+            None => println!("[{}] {}", level, record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Register the kernel logger and only let records of `level` or above through to
+/// both serial and the screen.
+///
+/// Calling it again only changes the level.
+pub fn init_logger(level: LevelFilter) {
+    // the logger can only be set once, later calls just fail
+    let _ = log::set_logger(&LOGGER);
+    match level.to_level() {
+        Some(level) => {
+            serial::set_min_level(level);
+            vga_buffer::set_min_level(level);
+        }
+        None => log::set_max_level(LevelFilter::Off),
+    }
+}
+
+/// The minimum level of one log sink, `Info` until it is changed
+pub(crate) struct SinkLevel(AtomicUsize);
+
+impl SinkLevel {
+    pub(crate) const fn new() -> Self {
+        SinkLevel(AtomicUsize::new(Level::Info as usize))
+    }
+
+    pub(crate) fn get(&self) -> Level {
+        match self.0.load(Ordering::Relaxed) {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    /// Change the level, the `log` macros skip records that no sink wants
+    pub(crate) fn set(&self, level: Level) {
+        self.0.store(level as usize, Ordering::Relaxed);
+        let max = serial::min_level().max(vga_buffer::min_level());
+        log::set_max_level(max.to_level_filter());
+    }
+}
+
+#[test_case]
+fn test_level_filter() {
+    init_logger(LevelFilter::Warn);
+    assert!(log::log_enabled!(Level::Error));
+    assert!(log::log_enabled!(Level::Warn));
+    assert!(!log::log_enabled!(Level::Info));
+    assert!(!log::log_enabled!(Level::Debug));
+    log::error!("test_level_filter error");
+    log::warn!("test_level_filter warning");
+    log::info!("test_level_filter info, should be dropped");
+
+    init_logger(LevelFilter::Info);
+    assert!(log::log_enabled!(Level::Info));
+}
+
+#[test_case]
+fn test_sink_levels() {
+    const MESSAGE: &str = "test_sink_levels debug";
+
+    serial::set_min_level(Level::Trace);
+    vga_buffer::set_min_level(Level::Warn);
+    assert!(log::log_enabled!(Level::Trace));
+
+    serial::start_capture();
+    log::debug!("{}", MESSAGE);
+    serial::stop_capture();
+    init_logger(LevelFilter::Info);
+
+    crate::assert_serial_contains!("[DEBUG] test_sink_levels debug\n");
+    assert!(!vga_buffer::screen_contains(MESSAGE));
+}
@@ -4,6 +4,7 @@
 #![test_runner(rust_os::test_runner)]
 // the custom test framework feature generates a main function that calls test_runner, which is ignored by `![no_main]`
 #![reexport_test_harness_main = "test_main"]
+#![feature(alloc_error_handler)]
 
 extern crate alloc;
 
@@ -12,12 +13,20 @@ use alloc::rc::Rc;
 use alloc::vec;
 use alloc::vec::Vec;
 use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
 use core::panic::PanicInfo;
-use x86_64::structures::paging::{Page, PageTable, Translate};
+#[cfg(not(test))]
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::structures::paging::Page;
 use x86_64::VirtAddr;
 
 use rust_os::memory::BootInfoFrameAllocator;
-use rust_os::{allocator, memory, println};
+#[cfg(not(test))]
+use rust_os::serial_println;
+use rust_os::task::{executor::Executor, Task};
+use rust_os::{
+    acpi, allocator, apic, cmdline, logger, memory, println, shell, smp, util, vga_buffer, watchdog,
+};
 
 entry_point!(kernel_main);
 
@@ -25,22 +34,63 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("Hello World{}", "!");
 
     rust_os::init();
+    rust_os::stack_canary::init(boot_info);
+
+    let args = cmdline::parse(cmdline::BOOT_CMDLINE);
+    if let Some(level) = args.log_level() {
+        logger::init_logger(level);
+    }
+    // `watchdog=<ticks>` panics if the executor stops running for that long
+    if let Some(ticks) = args.get("watchdog").and_then(|ticks| ticks.parse().ok()) {
+        watchdog::arm(ticks);
+    }
 
     /* Init mapper, level-4 page table instance and other memory related things */
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
     // let mut frame_allocator = memory::EmptyFrameAllocator;
+    vga_buffer::remap(&mut mapper, &mut frame_allocator).expect("remapping the VGA buffer failed");
+    let stats = memory::memory_stats(&boot_info.memory_map);
+    println!(
+        "memory: {} usable of {}, {} frames",
+        util::format_bytes(stats.usable_bytes),
+        util::format_bytes(stats.total_bytes),
+        stats.frame_count
+    );
+
+    /* Find the ACPI tables */
+    if let Err(error) = unsafe { acpi::init(phys_mem_offset) } {
+        println!("ACPI tables not found: {:?}", error);
+    }
 
     /* Test paging and memory mapping */
     let page = Page::containing_address(VirtAddr::new(0xdeadbeaf000));
     memory::create_example_mapping(page, &mut mapper, &mut frame_allocator);
     let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
     unsafe { page_ptr.offset(400).write_volatile(0x_f021_f077_f065_f04e) };
+    memory::dump_mapping(page.start_address(), &mapper);
 
     /* Init heap allocator */
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
 
+    /* Switch to the APIC if asked to */
+    if cfg!(feature = "apic") || args.flag("apic") {
+        match apic::init(&mut mapper, &mut frame_allocator) {
+            Ok(()) => println!("using the APIC"),
+            Err(error) => println!("APIC not available, keeping the PIC: {:?}", error),
+        }
+    }
+
+    /* Bring up the other CPUs, they only halt for now */
+    if apic::is_enabled() {
+        match smp::start_aps(&mut mapper, &mut frame_allocator) {
+            Ok(started) => println!("{} CPUs, started {} APs", smp::cpu_count(), started),
+            Err(error) => println!("starting the APs failed: {:?}", error),
+        }
+    }
+
     /* Test heap allocation */
     // allocate a number on the heap
     let heap_value = Box::new(41);
@@ -64,6 +114,11 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
         Rc::strong_count(&cloned_reference)
     );
 
+    /* Spawn async tasks, they run once the executor is started at the end */
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(example_task()));
+    executor.spawn(Task::new(shell::run()));
+
     // invoke a breakpoint exception
     // x86_64::instructions::interrupts::int3();
 
@@ -73,14 +128,54 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     println!("I did not crash!");
-    rust_os::hlt_loop();
+    executor.run();
+}
+
+async fn async_number() -> u32 {
+    42
 }
 
+async fn example_task() {
+    let number = async_number().await;
+    println!("async number: {}", number);
+}
+
+// set by the first panic, so a panic while showing the panic screen does not recurse
+#[cfg(not(test))]
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 /// This function is called on panic
 #[cfg(not(test))] // when not in test mode
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    x86_64::instructions::interrupts::disable();
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        serial_println!("PANIC while handling a panic: {}", info);
+        rust_os::hlt_loop();
+    }
+    // serial first, it works even if the screen can not be painted
+    serial_println!("KERNEL PANIC: {}", info);
+    vga_buffer::restore_color_after_panic();
+    if !vga_buffer::show_panic_screen(info) {
+        serial_println!("VGA writer is locked, no panic screen");
+    }
+    // only to serial, printing to the screen would scroll the panic screen away
+    serial_println!("Backtrace:");
+    rust_os::backtrace::for_each_frame(|index, address| {
+        serial_println!("#{}  {:#x}", index, address);
+    });
+    // for automated runs, which would otherwise wait for a timeout
+    if cfg!(feature = "panic-reboot") {
+        serial_println!("rebooting");
+        rust_os::reboot();
+    }
+    rust_os::hlt_loop();
+}
+
+/// This function is called when the heap can not satisfy an allocation
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    allocator::report_alloc_error(layout);
     rust_os::hlt_loop();
 }
 
@@ -1,12 +1,21 @@
+use crate::cpu::{self, CpuFeature};
+use crate::msr::{self, EFER_NO_EXECUTE_ENABLE, IA32_EFER};
+use crate::println;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{MapToError, UnmapError};
 use x86_64::structures::paging::page_table::FrameError;
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags as Flags, PhysFrame,
-    Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags as Flags, PhysFrame, Size2MiB, Size4KiB, Translate,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
+mod bitmap;
+
+pub use bitmap::BitmapFrameAllocator;
+
 /// Initialize a new OffsetPageTable
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -33,19 +42,177 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr
 }
 
+/// Map `page` to `frame` with the given flags and flush it from the TLB.
+///
+/// `flags` decides how the page can be accessed, e.g. leave out `WRITABLE`
+/// for a read-only mapping or add `NO_EXECUTE` or `USER_ACCESSIBLE`.
+///
+/// ## Safety
+///
+/// `frame` must not be in use for anything else, e.g. as a heap or page table
+/// frame, unless the caller makes sure that both users agree on its content.
+pub unsafe fn map_page(
+    page: Page,
+    frame: PhysFrame,
+    flags: Flags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    Ok(())
+}
+
+/// Map `page` to the device registers or device memory at `frame`.
+///
+/// The page is mapped with `NO_CACHE` and `WRITE_THROUGH`. With caching, reads
+/// could return a stale value instead of the current state of the device, and
+/// writes could reach the device late, in a different order or not at all when
+/// a later write to the same line replaces them.
+///
+/// ## Safety
+///
+/// `frame` must be device memory that nothing else in the kernel accesses in a
+/// conflicting way, see `map_page`.
+pub unsafe fn map_mmio(
+    page: Page,
+    frame: PhysFrame,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    map_page(page, frame, flags, mapper, frame_allocator)
+}
+
+/// Map `page` to the VGA text buffer, just for testing
 pub fn create_example_mapping(
     page: Page,
     mapper: &mut OffsetPageTable,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) {
     let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    unsafe { map_mmio(page, frame, mapper, frame_allocator) }.expect("map_to failed");
+}
+
+/// Remove the mapping of the given page and flush it from the TLB.
+///
+/// Returns the frame the page was mapped to, so that the caller can deallocate it.
+pub fn unmap_page(page: Page, mapper: &mut impl Mapper<Size4KiB>) -> Result<PhysFrame, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.flush();
+    Ok(frame)
+}
+
+/// Map `count` pages starting at `start` to newly allocated frames with the given flags.
+///
+/// Either all pages are mapped or none: if one of them fails, the pages mapped before
+/// are unmapped again, their frames are deallocated and the error of the failing page
+/// is returned. Page table frames allocated on the way are kept.
+pub fn map_range(
+    start: Page,
+    count: usize,
+    flags: Flags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<(), MapToError<Size4KiB>> {
+    for (mapped, page) in Page::range(start, start + count as u64).enumerate() {
+        let result = match frame_allocator.allocate_frame() {
+            // the frame was just allocated, so nothing else uses it
+            Some(frame) => unsafe { map_page(page, frame, flags, mapper, frame_allocator) }
+                .inspect_err(|_| unsafe { frame_allocator.deallocate_frame(frame) }),
+            None => Err(MapToError::FrameAllocationFailed),
+        };
+        if let Err(error) = result {
+            // the pages were just mapped, so unmapping them can not fail
+            let unmapped = unmap_range(start, mapped, mapper, frame_allocator);
+            debug_assert!(unmapped.is_ok(), "rolling back map_range failed");
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Unmap `count` pages starting at `start` and deallocate the frames they were mapped to
+pub fn unmap_range(
+    start: Page,
+    count: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<(), UnmapError> {
+    for page in Page::range(start, start + count as u64) {
+        let frame = unmap_page(page, mapper)?;
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+    Ok(())
+}
+
+/// Make the CPU honor the `NO_EXECUTE` page flag, if it supports it.
+///
+/// Without this the flag is a reserved bit and setting it makes the page fault on every access.
+pub fn enable_no_execute() {
+    if !cpu::has_feature(CpuFeature::Nx) {
+        return;
+    }
+    // every CPU with NX has MSRs
+    unsafe {
+        let efer = IA32_EFER.read();
+        IA32_EFER.write(efer | EFER_NO_EXECUTE_ENABLE);
+    }
+}
+
+/// Whether the `NO_EXECUTE` page flag can be used
+pub fn no_execute_enabled() -> bool {
+    msr::read_efer().is_some_and(|efer| efer & EFER_NO_EXECUTE_ENABLE != 0)
+}
+
+/// Start of the virtual address range that `allocate_stack` hands out
+const STACKS_START: u64 = 0x_6666_6666_0000;
+
+/// Bounds of a stack mapped by `allocate_stack`
+#[derive(Debug, Clone, Copy)]
+pub struct StackBounds {
+    start: VirtAddr,
+    end: VirtAddr,
+}
+
+impl StackBounds {
+    /// Lowest address of the stack
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// First address above the stack, the initial stack pointer
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+}
+
+/// Map a new stack of `pages` pages with an unmapped guard page below it.
+///
+/// The stack grows down, so an overflow runs into the guard page and
+/// page faults instead of silently overwriting the memory below. On failure
+/// nothing stays mapped, like with `map_range`, but the address range stays
+/// reserved and is not handed out again.
+pub fn allocate_stack(
+    pages: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<StackBounds, MapToError<Size4KiB>> {
+    static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+
+    // reserve the address range including the guard page
+    let size = (pages as u64 + 1) * Page::<Size4KiB>::SIZE;
+    let guard_page =
+        Page::containing_address(VirtAddr::new(NEXT_STACK.fetch_add(size, Ordering::SeqCst)));
+    let stack_start = guard_page + 1;
+    let stack_end = stack_start + pages as u64;
+
     let flags = Flags::PRESENT | Flags::WRITABLE;
+    map_range(stack_start, pages, flags, mapper, frame_allocator)?;
 
-    let map_to_result = unsafe {
-        // FIXME: this is not safe, just for testing
-        mapper.map_to(page, frame, flags, frame_allocator)
-    };
-    map_to_result.expect("map_to failed").flush();
+    Ok(StackBounds {
+        start: stack_start.start_address(),
+        end: stack_end.start_address(),
+    })
 }
 
 /// A FrameAllocator that always returns `None`.
@@ -57,10 +224,23 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// Marks the end of the free list of `BootInfoFrameAllocator`
+const NO_FRAME: u64 = u64::MAX;
+
 /// A FrameAllocator that returns usable frames frames from the bootloader's memory map.
+///
+/// Deallocated frames are kept in a free list and handed out again before any new frame.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
+    physical_memory_offset: VirtAddr,
     next: usize,
+    // successful allocations, including frames taken from the free list
+    frames_allocated: usize,
+    // the most recently freed frame, each frame on the list stores
+    // the start address of the next one (or `NO_FRAME`) in its first 8 bytes
+    free_list: Option<PhysFrame>,
+    // whether frames are zeroed before they are handed out, see `set_zero_frames`
+    zero_frames: bool,
 }
 
 impl BootInfoFrameAllocator {
@@ -68,81 +248,292 @@ impl BootInfoFrameAllocator {
     ///
     /// This function is unsafe because the caller must guarantee that the passed
     /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+    /// as `USABLE` in it are really unused. Also, the complete physical memory must be
+    /// mapped to virtual memory at the passed `physical_memory_offset`.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
         BootInfoFrameAllocator {
             memory_map,
+            physical_memory_offset,
             next: 0,
+            frames_allocated: 0,
+            free_list: None,
+            zero_frames: true,
         }
     }
 
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        // eg. [0..10, 20..30, 40..50] --flat_map-> [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 20, 21 ...] --step_by(5)-> [0, 5, 20, 25...]
-        // 4096 == 4KiB == the page size
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    /// Turn zeroing frames before they are handed out on or off, on by default.
+    ///
+    /// Without it a frame still holds whatever was stored in it before, e.g. stale data
+    /// of a deallocated kernel page. Zeroing costs one 4 KiB write per frame, so it can
+    /// be turned off for allocations that overwrite the whole frame anyway.
+    pub fn set_zero_frames(&mut self, enabled: bool) {
+        self.zero_frames = enabled;
+    }
+
+    /// Number of frames handed out so far, frames that were deallocated are not subtracted.
+    pub fn frames_allocated(&self) -> usize {
+        self.frames_allocated
+    }
+
+    /// Estimate of the frames that can still be allocated.
+    ///
+    /// Counts the usable frames of the memory map that were not handed out yet and
+    /// walks the free list, so it is not cheap.
+    pub fn remaining(&self) -> usize {
+        let fresh = self.usable_frames().count().saturating_sub(self.next);
+        let mut freed = 0;
+        let mut current = self.free_list;
+        while let Some(free_frame) = current {
+            freed += 1;
+            current = self.next_free(free_frame);
+        }
+        fresh + freed
+    }
+
+    /// Returns an iterator over all usable frames of the memory map, in address order.
+    ///
+    /// It includes the frames that were handed out already and does not allocate
+    /// anything, so other allocators can be built on the same memory map.
+    pub fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        usable_frames(self.memory_map)
+    }
+
+    /// Returns a pointer to the start of the frame through the physical memory mapping.
+    fn frame_ptr(&self, frame: PhysFrame) -> *mut u64 {
+        let virt = self.physical_memory_offset + frame.start_address().as_u64();
+        virt.as_mut_ptr()
+    }
+
+    /// Removes the first frame from the free list.
+    fn pop_free_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.free_list?;
+        self.free_list = self.next_free(frame);
+        Some(frame)
+    }
+
+    /// Zeroes the frame through the physical memory mapping if `zero_frames` is set.
+    fn zero_if_enabled(&self, frame: PhysFrame) {
+        if self.zero_frames {
+            // do not leak the old content (and our list pointer) into the new mapping
+            unsafe {
+                self.frame_ptr(frame)
+                    .write_bytes(0, frame.size() as usize / 8)
+            };
+        }
+    }
+
+    /// Whether the frame is currently on the free list.
+    fn is_free(&self, frame: PhysFrame) -> bool {
+        let mut current = self.free_list;
+        while let Some(free_frame) = current {
+            if free_frame == frame {
+                return true;
+            }
+            current = self.next_free(free_frame);
+        }
+        false
+    }
+
+    /// The frame after `frame` on the free list.
+    fn next_free(&self, frame: PhysFrame) -> Option<PhysFrame> {
+        match unsafe { self.frame_ptr(frame).read() } {
+            NO_FRAME => None,
+            addr => Some(PhysFrame::containing_address(PhysAddr::new(addr))),
+        }
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = self.pop_free_frame() {
+            self.frames_allocated += 1;
+            self.zero_if_enabled(frame);
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
+        match frame {
+            Some(frame) => {
+                self.next += 1;
+                self.frames_allocated += 1;
+                self.zero_if_enabled(frame);
+            }
+            None => log::warn!(
+                "out of physical frames, all {} usable frames are allocated",
+                self.next
+            ),
+        }
         frame
     }
 }
 
-/*
+/// Hands out 2 MiB frames for huge pages, made of 512 contiguous usable 4 KiB frames.
+///
+/// The usable frames that are skipped to reach the next 2 MiB boundary go to the
+/// free list, so they are not lost.
+unsafe impl FrameAllocator<Size2MiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        const FRAMES_PER_HUGE_FRAME: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize;
+
+        // (index of the first frame, its address, number of contiguous frames)
+        let mut run: Option<(usize, u64, usize)> = None;
+        let memory_map = self.memory_map;
+        for (index, frame) in usable_frames(memory_map).enumerate().skip(self.next) {
+            let addr = frame.start_address().as_u64();
+            run = match run {
+                Some((start, start_addr, len))
+                    if addr == start_addr + len as u64 * Size4KiB::SIZE =>
+                {
+                    Some((start, start_addr, len + 1))
+                }
+                _ if addr % Size2MiB::SIZE == 0 => Some((index, addr, 1)),
+                _ => None,
+            };
+            if let Some((start, start_addr, FRAMES_PER_HUGE_FRAME)) = run {
+                for skipped in usable_frames(memory_map)
+                    .skip(self.next)
+                    .take(start - self.next)
+                {
+                    unsafe { self.deallocate_frame(skipped) };
+                }
+                self.next = start + FRAMES_PER_HUGE_FRAME;
+                self.frames_allocated += FRAMES_PER_HUGE_FRAME;
+                return Some(PhysFrame::containing_address(PhysAddr::new(start_addr)));
+            }
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        debug_assert!(!self.is_free(frame), "frame {:?} deallocated twice", frame);
+        let next = match self.free_list {
+            Some(free_frame) => free_frame.start_address().as_u64(),
+            None => NO_FRAME,
+        };
+        self.frame_ptr(frame).write(next);
+        self.free_list = Some(frame);
+    }
+}
+
+/// Returns an iterator over the usable frames specified in the memory map.
+fn usable_frames(memory_map: &MemoryMap) -> impl Iterator<Item = PhysFrame> + Clone + '_ {
+    // get usable regions from memory map
+    let regions = memory_map.iter();
+    let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
+    // map each region to its address range
+    let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+    // transform to an iterator of frame start addresses
+    // eg. [0..10, 20..30, 40..50] --flat_map-> [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 20, 21 ...] --step_by(5)-> [0, 5, 20, 25...]
+    // 4096 == 4KiB == the page size
+    let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+    // create `PhysFrame` types from the start addresses
+    frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+}
+
+/// Sizes of the physical memory regions in the bootloader's memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes covered by any region
+    pub total_bytes: u64,
+    /// Bytes in `Usable` regions, the ones `BootInfoFrameAllocator` hands out
+    pub usable_bytes: u64,
+    /// Bytes in `Reserved` regions, which belong to the firmware or to devices
+    pub reserved_bytes: u64,
+    /// Number of usable 4 KiB frames
+    pub frame_count: usize,
+}
+
+/// Sum up the regions of the memory map.
+pub fn memory_stats(memory_map: &MemoryMap) -> MemoryStats {
+    let bytes_of = |region_type: Option<MemoryRegionType>| -> u64 {
+        memory_map
+            .iter()
+            .filter(|r| region_type.map_or(true, |t| r.region_type == t))
+            .map(|r| r.range.end_addr() - r.range.start_addr())
+            .sum()
+    };
+    MemoryStats {
+        total_bytes: bytes_of(None),
+        usable_bytes: bytes_of(Some(MemoryRegionType::Usable)),
+        reserved_bytes: bytes_of(Some(MemoryRegionType::Reserved)),
+        frame_count: usable_frames(memory_map).count(),
+    }
+}
+
 /// Translate the given virtual address to the mapped physical address, or
 /// `None` if the address is not mapped.
-///
-/// This function is unsafe because the caller must guarantee that the
-/// complete physical memory is mapped to virtual memory at the passed `physical_memory_offset`.
-pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    translate_addr_inner(addr, physical_memory_offset)
+pub fn translate_addr(addr: VirtAddr, mapper: &OffsetPageTable) -> Option<PhysAddr> {
+    mapper.translate_addr(addr)
 }
 
-/// Private function that is called by `translate_addr`.
-///
-/// This function is safe to limit the scope of `unsafe` because
-/// Rust treats the whole body of unsafe functions as an unsafe block.
-/// This function must only be reachable through `unsafe fn` from outside of this module.
-fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    // read the active level 4 frame from the CR3 register
-    let (level_4_table_frame, _) = Cr3::read();
+/// Print how the given virtual address is resolved, level by level, for debugging.
+pub fn dump_mapping(addr: VirtAddr, mapper: &OffsetPageTable) {
+    println!("{:?} -> {:?}", addr, translate_addr(addr, mapper));
+    walk_page_table(addr, mapper);
+}
 
+/// Print the page table indices of the address and the entry of each level, for debugging.
+///
+/// Stops at the first entry that is not present or maps a huge page. The tables are
+/// read through the physical memory offset of the mapper. Returns the number of
+/// levels visited.
+pub fn walk_page_table(addr: VirtAddr, mapper: &OffsetPageTable) -> usize {
     let table_indexes = [
         addr.p4_index(),
         addr.p3_index(),
         addr.p2_index(),
         addr.p1_index(),
     ];
-    let mut frame = level_4_table_frame;
+    println!(
+        "  P4 {} P3 {} P2 {} P1 {} offset {:#x}",
+        u16::from(table_indexes[0]),
+        u16::from(table_indexes[1]),
+        u16::from(table_indexes[2]),
+        u16::from(table_indexes[3]),
+        u16::from(addr.page_offset())
+    );
+
+    // read the active level 4 frame from the CR3 register
+    let (mut frame, _) = Cr3::read();
+    let mut visited = 0;
 
     // traverse the multi-level page table
-    for &index in &table_indexes {
-        // convert the frame into a page table reference
-        let virt = physical_memory_offset + frame.start_address().as_u64();
-        let table_ptr: *const PageTable = virt.as_mut_ptr();
+    for (level, &index) in (1..=4).rev().zip(&table_indexes) {
+        let virt = mapper.phys_offset() + frame.start_address().as_u64();
+        let table_ptr: *const PageTable = virt.as_ptr();
+        // the complete physical memory is mapped at `phys_offset` for the mapper to work
         let table = unsafe { &*table_ptr };
+        visited += 1;
 
-        // read the page table entry and update `frame`
         let entry = &table[index];
+        println!(
+            "  P{} entry {}: {:?} {:?}",
+            level,
+            u16::from(index),
+            entry.addr(),
+            entry.flags()
+        );
+        if level == 1 {
+            // bit 7 is the PAT bit here, not a huge page, so `frame()` can not be used
+            if entry.flags().contains(Flags::PRESENT) {
+                println!("  page at {:?}", entry.addr());
+            } else {
+                println!("  not present");
+            }
+            break;
+        }
         frame = match entry.frame() {
             Ok(frame) => frame,
-            Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
+            Err(FrameError::FrameNotPresent) => {
+                println!("  not present");
+                break;
+            }
+            Err(FrameError::HugeFrame) => {
+                println!("  huge page");
+                break;
+            }
         };
     }
-
-    Some(frame.start_address() + u64::from(addr.page_offset()))
+    visited
 }
-*/
@@ -0,0 +1,225 @@
+use super::usable_frames;
+use bootloader::bootinfo::MemoryMap;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const BITS_PER_WORD: usize = 64;
+// a 2 MiB frame covers 512 frames, which are 8 aligned words of the bitmap
+const WORDS_PER_HUGE_FRAME: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize / BITS_PER_WORD;
+
+/// A FrameAllocator with one bit per frame, set while the frame is in use.
+///
+/// The bitmap covers the physical memory up to the highest usable frame and is
+/// stored in the first usable frames that are large enough for it. Deallocated
+/// frames can be handed out again right away, the lowest free frame comes first.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    physical_memory_offset: VirtAddr,
+    // all words below it are full, so the search for a free frame starts here
+    next_word: usize,
+    free_frames: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a FrameAllocator over the usable frames of the memory map.
+    ///
+    /// Returns `None` if no usable region can hold the bitmap. This function is unsafe
+    /// for the same reasons as `BootInfoFrameAllocator::init`.
+    pub unsafe fn init(
+        memory_map: &'static MemoryMap,
+        physical_memory_offset: VirtAddr,
+    ) -> Option<Self> {
+        Self::from_frames(usable_frames(memory_map), physical_memory_offset)
+    }
+
+    /// Create a FrameAllocator that hands out the given frames, some of them keep the bitmap.
+    ///
+    /// Returns `None` if `frames` has no run of contiguous frames large enough for the
+    /// bitmap. This function is unsafe because the caller must guarantee that all the
+    /// frames are unused, that each of them is only passed once and that the complete
+    /// physical memory is mapped at `physical_memory_offset`.
+    pub unsafe fn from_frames(
+        frames: impl Iterator<Item = PhysFrame> + Clone,
+        physical_memory_offset: VirtAddr,
+    ) -> Option<Self> {
+        let end = frames
+            .clone()
+            .map(|frame| frame.start_address() + frame.size())
+            .max()?;
+        let frame_count = (end.as_u64() / Size4KiB::SIZE) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_frames = (words * 8).div_ceil(Size4KiB::SIZE as usize);
+        let bitmap_start = find_contiguous(frames.clone(), bitmap_frames)?;
+
+        let bitmap_ptr = (physical_memory_offset + bitmap_start.as_u64()).as_mut_ptr::<u64>();
+        let bitmap = core::slice::from_raw_parts_mut(bitmap_ptr, words);
+        // everything that is not passed in is in use, including the bits after the last frame
+        bitmap.fill(u64::MAX);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            physical_memory_offset,
+            next_word: 0,
+            free_frames: 0,
+        };
+        for frame in frames {
+            allocator.set_allocated(frame_index(frame), false);
+            allocator.free_frames += 1;
+        }
+        let first_bitmap_frame = (bitmap_start.as_u64() / Size4KiB::SIZE) as usize;
+        for index in first_bitmap_frame..first_bitmap_frame + bitmap_frames {
+            allocator.set_allocated(index, true);
+            allocator.free_frames -= 1;
+        }
+        Some(allocator)
+    }
+
+    /// Number of frames that can still be allocated
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Whether `frame` is in use, frames that were never usable count as in use
+    pub fn is_allocated(&self, frame: PhysFrame) -> bool {
+        let index = frame_index(frame);
+        match self.bitmap.get(index / BITS_PER_WORD) {
+            Some(word) => word & bit(index) != 0,
+            None => true,
+        }
+    }
+
+    fn set_allocated(&mut self, index: usize, allocated: bool) {
+        let word = &mut self.bitmap[index / BITS_PER_WORD];
+        if allocated {
+            *word |= bit(index);
+        } else {
+            *word &= !bit(index);
+        }
+    }
+
+    // do not leak the old content into the new mapping, like `BootInfoFrameAllocator`
+    fn zero(&self, start: PhysAddr, size: u64) {
+        let ptr = (self.physical_memory_offset + start.as_u64()).as_mut_ptr::<u64>();
+        unsafe { ptr.write_bytes(0, size as usize / 8) };
+    }
+}
+
+fn frame_index(frame: PhysFrame) -> usize {
+    (frame.start_address().as_u64() / Size4KiB::SIZE) as usize
+}
+
+fn bit(index: usize) -> u64 {
+    1 << (index % BITS_PER_WORD)
+}
+
+/// Start of the first `count` frames in `frames` that follow each other
+fn find_contiguous(frames: impl Iterator<Item = PhysFrame>, count: usize) -> Option<PhysAddr> {
+    // (start address, number of contiguous frames)
+    let mut run: Option<(PhysAddr, usize)> = None;
+    for frame in frames {
+        let addr = frame.start_address();
+        run = match run {
+            Some((start, len)) if addr == start + len as u64 * Size4KiB::SIZE => {
+                Some((start, len + 1))
+            }
+            _ => Some((addr, 1)),
+        };
+        if let Some((start, len)) = run {
+            if len == count {
+                return Some(start);
+            }
+        }
+    }
+    None
+}
+
+/// Index of the first clear bit in `words`, starting the search at word `start`
+fn find_clear_bit(words: &[u64], start: usize) -> Option<usize> {
+    let offset = words[start..].iter().position(|&word| word != u64::MAX)?;
+    let word = start + offset;
+    Some(word * BITS_PER_WORD + words[word].trailing_ones() as usize)
+}
+
+/// Index of the first word of `count` aligned words that are all clear
+fn find_clear_words(words: &[u64], count: usize) -> Option<usize> {
+    words
+        .chunks_exact(count)
+        .position(|chunk| chunk.iter().all(|&word| word == 0))
+        .map(|chunk| chunk * count)
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let Some(index) = find_clear_bit(self.bitmap, self.next_word) else {
+            self.next_word = self.bitmap.len();
+            log::warn!("out of physical frames, the bitmap has no free frame");
+            return None;
+        };
+        self.next_word = index / BITS_PER_WORD;
+        self.set_allocated(index, true);
+        self.free_frames -= 1;
+        let frame = PhysFrame::containing_address(PhysAddr::new(index as u64 * Size4KiB::SIZE));
+        self.zero(frame.start_address(), frame.size());
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        debug_assert!(
+            self.is_allocated(frame),
+            "frame {:?} deallocated twice",
+            frame
+        );
+        let index = frame_index(frame);
+        self.set_allocated(index, false);
+        self.free_frames += 1;
+        self.next_word = self.next_word.min(index / BITS_PER_WORD);
+    }
+}
+
+/// Hands out 2 MiB frames for huge pages, made of 512 free frames on a 2 MiB boundary
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let word = find_clear_words(self.bitmap, WORDS_PER_HUGE_FRAME)?;
+        self.bitmap[word..word + WORDS_PER_HUGE_FRAME].fill(u64::MAX);
+        self.free_frames -= WORDS_PER_HUGE_FRAME * BITS_PER_WORD;
+        let start = PhysAddr::new((word * BITS_PER_WORD) as u64 * Size4KiB::SIZE);
+        self.zero(start, Size2MiB::SIZE);
+        Some(PhysFrame::containing_address(start))
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let word = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize / BITS_PER_WORD;
+        self.bitmap[word..word + WORDS_PER_HUGE_FRAME].fill(0);
+        self.free_frames += WORDS_PER_HUGE_FRAME * BITS_PER_WORD;
+        self.next_word = self.next_word.min(word);
+    }
+}
+
+#[test_case]
+fn test_find_clear_bit() {
+    let words = [u64::MAX, u64::MAX, 0b1011, 0];
+    assert_eq!(find_clear_bit(&words, 0), Some(2 * 64 + 2));
+    assert_eq!(find_clear_bit(&words, 3), Some(3 * 64));
+    assert_eq!(find_clear_bit(&[u64::MAX; 4], 0), None);
+}
+
+#[test_case]
+fn test_find_clear_words() {
+    let mut words = [0u64; 4 * WORDS_PER_HUGE_FRAME];
+    // one frame in use in each of the first two huge frames
+    words[3] = 1;
+    words[WORDS_PER_HUGE_FRAME] = 1 << 63;
+    assert_eq!(
+        find_clear_words(&words, WORDS_PER_HUGE_FRAME),
+        Some(2 * WORDS_PER_HUGE_FRAME)
+    );
+    // free words that are not aligned do not count
+    words[2 * WORDS_PER_HUGE_FRAME + 1] = 1;
+    words[3 * WORDS_PER_HUGE_FRAME + 7] = 1;
+    assert_eq!(find_clear_words(&words, WORDS_PER_HUGE_FRAME), None);
+}
@@ -0,0 +1,78 @@
+use crate::cpu::{self, CpuFeature};
+use core::arch::asm;
+
+/// A model specific register, by its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msr(pub u32);
+
+/// Enables long mode, NXE and friends
+pub const IA32_EFER: Msr = Msr(0xC000_0080);
+/// Physical address and enable bit of the local APIC
+pub const IA32_APIC_BASE: Msr = Msr(0x1B);
+/// The TSC value at which the local APIC timer fires in TSC-deadline mode
+pub const IA32_TSC_DEADLINE: Msr = Msr(0x6E0);
+
+/// Bits of `IA32_EFER`
+pub const EFER_LONG_MODE_ENABLE: u64 = 1 << 8;
+pub const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+impl Msr {
+    /// Read the register with `rdmsr`.
+    ///
+    /// This function is unsafe because reading an MSR the CPU does not have raises a
+    /// general protection fault, and some MSRs have side effects when read.
+    pub unsafe fn read(self) -> u64 {
+        let (high, low): (u32, u32);
+        asm!(
+            "rdmsr",
+            in("ecx") self.0,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags),
+        );
+        u64::from(high) << 32 | u64::from(low)
+    }
+
+    /// Write `value` to the register with `wrmsr`.
+    ///
+    /// This function is unsafe because writing an MSR the CPU does not have, or a value
+    /// with reserved bits set, raises a general protection fault. And most MSRs change
+    /// how the CPU works, e.g. `IA32_EFER`.
+    pub unsafe fn write(self, value: u64) {
+        asm!(
+            "wrmsr",
+            in("ecx") self.0,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// Whether the CPU has `rdmsr` and `wrmsr`
+pub fn is_supported() -> bool {
+    cpu::has_feature(CpuFeature::Msr)
+}
+
+/// Read `IA32_EFER`, `None` if the CPU does not support MSRs
+pub fn read_efer() -> Option<u64> {
+    // every CPU with long mode has EFER
+    is_supported().then(|| unsafe { IA32_EFER.read() })
+}
+
+/// Read `IA32_APIC_BASE`, `None` if the CPU does not support MSRs or has no local APIC
+pub fn read_apic_base() -> Option<u64> {
+    let supported = is_supported() && cpu::has_feature(CpuFeature::Apic);
+    supported.then(|| unsafe { IA32_APIC_BASE.read() })
+}
+
+#[test_case]
+fn test_efer_matches_no_execute() {
+    let efer = read_efer().expect("no MSR support");
+    // the kernel runs in long mode
+    assert_ne!(efer & EFER_LONG_MODE_ENABLE, 0);
+    // `init` sets NXE if the CPU supports it
+    let nxe = efer & EFER_NO_EXECUTE_ENABLE != 0;
+    assert_eq!(nxe, crate::memory::no_execute_enabled());
+    assert_eq!(nxe, cpu::has_feature(CpuFeature::Nx));
+}
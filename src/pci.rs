@@ -0,0 +1,153 @@
+use heapless::Vec;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+// I/O ports of the PCI configuration mechanism #1
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Maximum number of devices `scan` returns
+pub const MAX_DEVICES: usize = 32;
+
+// returned as vendor id when there is no device at that location
+const NO_VENDOR: u16 = 0xFFFF;
+// bit 7 of the header type marks a device with more than one function
+const MULTIFUNCTION: u8 = 0x80;
+
+/// A function found on the PCI bus
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    class_code: u8,
+    subclass: u8,
+    header_type: u8,
+    bars: [u32; 6],
+}
+
+impl PciDevice {
+    fn read(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+        let id = read_config(bus, device, function, 0x00);
+        let vendor_id = id as u16;
+        if vendor_id == NO_VENDOR {
+            return None;
+        }
+        let class = read_config(bus, device, function, 0x08);
+        let header_type = (read_config(bus, device, function, 0x0C) >> 16) as u8;
+        let mut bars = [0; 6];
+        for (n, bar) in bars.iter_mut().enumerate() {
+            *bar = read_config(bus, device, function, 0x10 + 4 * n as u8);
+        }
+        Some(PciDevice {
+            bus,
+            device,
+            function,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class_code: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            header_type,
+            bars,
+        })
+    }
+
+    /// Base class of the device, e.g. 0x01 for mass storage or 0x06 for bridges
+    pub fn class_code(&self) -> u8 {
+        self.class_code
+    }
+
+    pub fn subclass(&self) -> u8 {
+        self.subclass
+    }
+
+    /// Raw value of the base address register `n`.
+    ///
+    /// Returns `None` if the header layout of the device has no such register,
+    /// normal devices have 6 and PCI-to-PCI bridges only 2.
+    pub fn bar(&self, n: usize) -> Option<u32> {
+        let count = match self.header_type & !MULTIFUNCTION {
+            0x00 => 6,
+            0x01 => 2,
+            _ => 0,
+        };
+        if n < count {
+            Some(self.bars[n])
+        } else {
+            None
+        }
+    }
+
+    pub fn is_multifunction(&self) -> bool {
+        self.header_type & MULTIFUNCTION != 0
+    }
+}
+
+/// Read the 32 bit register at `offset` of the configuration space of a function
+fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    // bit 31 enables the access, the lowest 2 bits of the offset must be zero
+    let address = 0x8000_0000
+        | u32::from(bus) << 16
+        | u32::from(device) << 11
+        | u32::from(function) << 8
+        | u32::from(offset & 0xFC);
+    let mut address_port: Port<u32> = Port::new(CONFIG_ADDRESS);
+    let mut data_port: Port<u32> = Port::new(CONFIG_DATA);
+    // the address and data access must not be split by another config access
+    interrupts::without_interrupts(|| unsafe {
+        address_port.write(address);
+        data_port.read()
+    })
+}
+
+/// Check every bus, device and function and return the devices found.
+///
+/// Stops when `MAX_DEVICES` devices are found.
+pub fn scan() -> Vec<PciDevice, MAX_DEVICES> {
+    let mut devices = Vec::new();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let first = match PciDevice::read(bus, device, 0) {
+                Some(first) => first,
+                None => continue,
+            };
+            let functions = if first.is_multifunction() { 8 } else { 1 };
+            if devices.push(first).is_err() {
+                return devices;
+            }
+            for function in 1..functions {
+                if let Some(found) = PciDevice::read(bus, device, function) {
+                    if devices.push(found).is_err() {
+                        return devices;
+                    }
+                }
+            }
+        }
+    }
+    devices
+}
+
+#[test_case]
+fn test_scan_finds_host_bridge() {
+    use crate::serial_println;
+
+    let devices = scan();
+    for device in &devices {
+        serial_println!(
+            "pci {:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}:{:02x}",
+            device.bus,
+            device.device,
+            device.function,
+            device.vendor_id,
+            device.device_id,
+            device.class_code(),
+            device.subclass()
+        );
+    }
+    // every QEMU machine has a host bridge (class 0x06, subclass 0x00)
+    assert!(devices
+        .iter()
+        .any(|device| device.class_code() == 0x06 && device.subclass() == 0x00));
+}
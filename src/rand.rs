@@ -0,0 +1,124 @@
+use crate::cpu::{self, CpuFeature};
+use core::arch::asm;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// How often `RDRAND` is retried when it reports that no random value was ready
+const RDRAND_RETRIES: usize = 10;
+
+// state of the fallback generator used by `rand_u64`, 0 until it is seeded
+static FALLBACK_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a random number from `RDRAND`, or from a PRNG seeded with the TSC
+/// if the CPU does not support it or keeps failing.
+///
+/// The fallback is not suitable for anything security related.
+pub fn rand_u64() -> u64 {
+    if cpu::has_feature(CpuFeature::Rdrand) {
+        if let Some(value) = rdrand() {
+            return value;
+        }
+    }
+    fallback_u64()
+}
+
+fn rdrand() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ok: u8;
+        // the carry flag is cleared if the hardware had no random value ready
+        unsafe {
+            asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            )
+        };
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn fallback_u64() -> u64 {
+    let mut state = FALLBACK_STATE.load(Ordering::Relaxed);
+    loop {
+        let current = if state == 0 { tsc_seed() } else { state };
+        let next = xorshift(current);
+        match FALLBACK_STATE.compare_exchange_weak(
+            state,
+            next,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return next,
+            Err(actual) => state = actual,
+        }
+    }
+}
+
+fn tsc_seed() -> u64 {
+    // xorshift gets stuck at 0
+    let tsc = unsafe { _rdtsc() };
+    tsc | 1
+}
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+/// A small and fast xorshift PRNG, not suitable for anything security related
+#[derive(Debug, Clone)]
+pub struct SmallRng {
+    state: u64,
+}
+
+impl SmallRng {
+    /// Create a generator with a fixed seed, to get the same numbers each run
+    pub fn from_seed(seed: u64) -> SmallRng {
+        SmallRng {
+            // xorshift gets stuck at 0
+            state: if seed == 0 { 1 } else { seed },
+        }
+    }
+
+    /// Create a generator seeded with `rand_u64`
+    pub fn new() -> SmallRng {
+        SmallRng::from_seed(rand_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = xorshift(self.state);
+        self.state
+    }
+
+    /// Returns a number in `[lo, hi)`, panics if the range is empty.
+    ///
+    /// Done with a modulo, so for large ranges low numbers are slightly more likely.
+    pub fn next_range(&mut self, lo: u64, hi: u64) -> u64 {
+        assert!(lo < hi, "empty range {}..{}", lo, hi);
+        lo + self.next_u64() % (hi - lo)
+    }
+}
+
+#[test_case]
+fn test_next_range_in_bounds() {
+    let mut rng = SmallRng::new();
+    for _ in 0..1000 {
+        let value = rng.next_range(5, 10);
+        assert!((5..10).contains(&value));
+    }
+}
+
+#[test_case]
+fn test_rand_u64_changes() {
+    // two equal values in a row are possible but very unlikely
+    let values = [rand_u64(), rand_u64(), rand_u64()];
+    assert!(values[0] != values[1] || values[1] != values[2]);
+}
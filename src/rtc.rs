@@ -0,0 +1,127 @@
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+// I/O ports of the CMOS, write the register number to the address port then read the data port
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// CMOS registers of the real time clock
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+// status register A: set while the clock is updating its registers
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+// status register B: values are binary instead of BCD / hours are 24-hour instead of 12-hour
+const BINARY_MODE: u8 = 0x04;
+const HOUR_24_MODE: u8 = 0x02;
+// set in the hours register for PM times in 12-hour mode
+const HOUR_PM: u8 = 0x80;
+
+/// Wall-clock time as kept by the CMOS real time clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut address_port: Port<u8> = Port::new(CMOS_ADDRESS);
+    let mut data_port: Port<u8> = Port::new(CMOS_DATA);
+    unsafe {
+        address_port.write(register);
+        data_port.read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0
+}
+
+/// Read the raw register values, in whatever format status register B says
+fn read_raw() -> DateTime {
+    while update_in_progress() {}
+    DateTime {
+        year: u16::from(read_register(REG_YEAR)),
+        month: read_register(REG_MONTH),
+        day: read_register(REG_DAY),
+        hour: read_register(REG_HOURS),
+        minute: read_register(REG_MINUTES),
+        second: read_register(REG_SECONDS),
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Read the current date and time from the real time clock
+pub fn read_rtc() -> DateTime {
+    // the address port selects the register for the next data read, an
+    // interrupt handler touching the CMOS in between would mess it up
+    let (mut time, status_b) = interrupts::without_interrupts(|| {
+        // the clock may update between reading two registers, so read until
+        // two reads in a row agree to not get a torn value
+        let mut time = read_raw();
+        loop {
+            let again = read_raw();
+            if again == time {
+                break;
+            }
+            time = again;
+        }
+        (time, read_register(REG_STATUS_B))
+    });
+
+    // the PM flag is not part of the BCD value
+    let pm = status_b & HOUR_24_MODE == 0 && time.hour & HOUR_PM != 0;
+    time.hour &= !HOUR_PM;
+
+    if status_b & BINARY_MODE == 0 {
+        time.second = bcd_to_binary(time.second);
+        time.minute = bcd_to_binary(time.minute);
+        time.hour = bcd_to_binary(time.hour);
+        time.day = bcd_to_binary(time.day);
+        time.month = bcd_to_binary(time.month);
+        time.year = u16::from(bcd_to_binary(time.year as u8));
+    }
+
+    // 12-hour mode counts 12, 1, ..., 11
+    if status_b & HOUR_24_MODE == 0 {
+        time.hour %= 12;
+        if pm {
+            time.hour += 12;
+        }
+    }
+
+    // the year register only holds the last two digits
+    time.year += 2000;
+    time
+}
+
+#[test_case]
+fn test_bcd_to_binary() {
+    assert_eq!(bcd_to_binary(0x00), 0);
+    assert_eq!(bcd_to_binary(0x09), 9);
+    assert_eq!(bcd_to_binary(0x59), 59);
+}
+
+#[test_case]
+fn test_read_rtc_in_range() {
+    let time = read_rtc();
+    assert!(time.year >= 2000 && time.year < 2100);
+    assert!((1..=12).contains(&time.month));
+    assert!((1..=31).contains(&time.day));
+    assert!(time.hour < 24);
+    assert!(time.minute < 60);
+    assert!(time.second < 60);
+}
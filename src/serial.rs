@@ -1,15 +1,74 @@
+use crate::logger::SinkLevel;
+use crate::sync::Mutex;
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use heapless::Deque;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use log::Level;
 use uart_16550::SerialPort;
 use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+// I/O ports of the first and the second serial interface
+const COM1: u16 = 0x3F8;
+const COM2: u16 = 0x2F8;
+// registers relative to the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const LINE_STATUS: u16 = 5;
+// interrupt enable register: interrupt when a received byte can be read
+const DATA_AVAILABLE_INTERRUPT: u8 = 1;
+// line status register: a received byte can be read / a byte was lost because the FIFO was full
+const DATA_READY: u8 = 1;
+const OVERRUN_ERROR: u8 = 1 << 1;
+// line status register: the next byte to send can be written
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Bytes the receive queue holds before further input is dropped
+const RECEIVE_QUEUE_SIZE: usize = 64;
+
+/// Bytes of output `start_capture` keeps, older output is dropped
+const CAPTURE_SIZE: usize = 1024;
+
+// bytes lost because the UART FIFO or the receive queue overflowed
+static OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+static LOG_LEVEL: SinkLevel = SinkLevel::new();
+
+/// Only write `log` records of `level` or more severe to serial, `Info` by default
+pub fn set_min_level(level: Level) {
+    LOG_LEVEL.set(level);
+}
+
+pub fn min_level() -> Level {
+    LOG_LEVEL.get()
+}
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
         // UART needs multiple I/O ports. we pass the first port to it, and it will calc all needed ports
         // 0x3F8 is the standard port number for the first serial interface
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
+        serial_port.init();
+        // received bytes are read by `serial_interrupt_handler` into RECEIVE_QUEUE
+        let mut interrupt_enable: Port<u8> = Port::new(COM1 + INTERRUPT_ENABLE);
+        unsafe { interrupt_enable.write(DATA_AVAILABLE_INTERRUPT) };
+        Mutex::new(serial_port)
+    };
+
+    /// Bytes received on the first serial interface that were not read yet
+    static ref RECEIVE_QUEUE: Mutex<Deque<u8, RECEIVE_QUEUE_SIZE>> = Mutex::new(Deque::new());
+
+    /// The latest output written to SERIAL1 while capturing
+    static ref CAPTURE: Mutex<Deque<u8, CAPTURE_SIZE>> = Mutex::new(Deque::new());
+
+    /// The second serial interface, for logging separately from the test output.
+    ///
+    /// When QEMU is started without a second serial device, the port reads as 0xFF,
+    /// so the "transmitter empty" bit looks set and the writes just go nowhere.
+    pub static ref SERIAL2: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM2) };
         serial_port.init();
         Mutex::new(serial_port)
     };
@@ -35,6 +94,50 @@ macro_rules! serial_println {
     };
 }
 
+/// Like `serial_print!`, but drops the output if `SERIAL1` is locked.
+///
+/// For interrupt and exception handlers, which may have interrupted a print on
+/// the same CPU and would wait for the lock forever.
+#[macro_export]
+macro_rules! serial_try_print {
+    ($($arg:tt)*) => {
+        $crate::serial::try_print(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial_try_println {
+    () => {
+        $crate::serial_try_print!("\n")
+    };
+    ($fmt:expr) => {
+        $crate::serial_try_print!(concat!($fmt, "\n"))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        $crate::serial_try_print!(concat!($fmt, "\n"), $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! serial2_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print2(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial2_println {
+    () => {
+        $crate::serial2_print!("\n")
+    };
+    ($fmt:expr) => {
+        $crate::serial2_print!(concat!($fmt, "\n"))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        $crate::serial2_print!(concat!($fmt, "\n"), $($arg)*)
+    };
+}
+
 pub fn _print(args: fmt::Arguments) {
     // avoid deadlocks
     interrupts::without_interrupts(|| {
@@ -42,5 +145,258 @@ pub fn _print(args: fmt::Arguments) {
             .lock()
             .write_fmt(args)
             .expect("Print to serial failed");
+        if CAPTURING.load(Ordering::Relaxed) {
+            capture(&mut CAPTURE.lock(), args);
+        }
+    });
+}
+
+/// Like `_print`, but drops the output instead of waiting if SERIAL1 is locked.
+///
+/// Returns whether the output was written.
+pub fn try_print(args: fmt::Arguments) -> bool {
+    interrupts::without_interrupts(|| match SERIAL1.try_lock() {
+        Some(mut serial) => {
+            if CAPTURING.load(Ordering::Relaxed) {
+                if let Some(mut buffer) = CAPTURE.try_lock() {
+                    capture(&mut buffer, args);
+                }
+            }
+            serial.write_fmt(args).is_ok()
+        }
+        None => false,
+    })
+}
+
+// whether the output to SERIAL1 is copied to CAPTURE
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// Start keeping a copy of everything written to SERIAL1, for `assert_serial_contains!`.
+///
+/// Output captured earlier is discarded. Only the last 1 KiB is kept.
+pub fn start_capture() {
+    interrupts::without_interrupts(|| {
+        CAPTURE.lock().clear();
+        CAPTURING.store(true, Ordering::Relaxed);
+    });
+}
+
+pub fn stop_capture() {
+    CAPTURING.store(false, Ordering::Relaxed);
+}
+
+/// Whether the output captured since `start_capture` contains `s`
+pub fn captured_contains(s: &str) -> bool {
+    let mut bytes = [0; CAPTURE_SIZE];
+    let len = interrupts::without_interrupts(|| {
+        let buffer = CAPTURE.lock();
+        for (byte, captured) in bytes.iter_mut().zip(buffer.iter()) {
+            *byte = *captured;
+        }
+        buffer.len()
+    });
+    s.is_empty()
+        || bytes[..len]
+            .windows(s.len())
+            .any(|window| window == s.as_bytes())
+}
+
+fn capture(buffer: &mut Deque<u8, CAPTURE_SIZE>, args: fmt::Arguments) {
+    struct Capture<'a>(&'a mut Deque<u8, CAPTURE_SIZE>);
+
+    impl fmt::Write for Capture<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for &byte in s.as_bytes() {
+                if self.0.is_full() {
+                    self.0.pop_front();
+                }
+                let _ = self.0.push_back(byte);
+            }
+            Ok(())
+        }
+    }
+
+    let _ = Capture(buffer).write_fmt(args);
+}
+
+/// Assert that the output captured since `serial::start_capture` contains `s`
+#[macro_export]
+macro_rules! assert_serial_contains {
+    ($s:expr) => {
+        assert!(
+            $crate::serial::captured_contains($s),
+            "serial output does not contain {:?}",
+            $s
+        )
+    };
+}
+
+pub fn _print2(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        SERIAL2
+            .lock()
+            .write_fmt(args)
+            .expect("Print to serial failed");
     });
 }
+
+fn line_status() -> u8 {
+    let mut line_status: Port<u8> = Port::new(COM1 + LINE_STATUS);
+    unsafe { line_status.read() }
+}
+
+/// Write to COM1 through the ports directly, without the lock of SERIAL1.
+///
+/// For reports about a stuck SERIAL1 lock. The output is not captured and can end
+/// up in the middle of the output of whoever holds the lock.
+pub fn write_raw(args: fmt::Arguments) {
+    struct RawSerial;
+
+    impl fmt::Write for RawSerial {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let mut data: Port<u8> = Port::new(COM1 + DATA);
+            for byte in s.bytes() {
+                while line_status() & TRANSMIT_EMPTY == 0 {
+                    core::hint::spin_loop();
+                }
+                unsafe { data.write(byte) };
+            }
+            Ok(())
+        }
+    }
+
+    let _ = RawSerial.write_fmt(args);
+}
+
+/// Move all bytes from the UART receive FIFO into the receive queue.
+///
+/// Called by the serial interrupt handler, reads the ports directly so it
+/// never waits for the lock of SERIAL1.
+pub(crate) fn drain_receive_fifo() {
+    let mut data: Port<u8> = Port::new(COM1 + DATA);
+    let mut queue = RECEIVE_QUEUE.lock();
+    loop {
+        let status = line_status();
+        if status & OVERRUN_ERROR != 0 {
+            OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        }
+        if status & DATA_READY == 0 {
+            break;
+        }
+        let byte = unsafe { data.read() };
+        if queue.push_back(byte).is_err() {
+            OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Number of received bytes that were lost, either in the UART or because the queue was full
+pub fn overrun_count() -> u64 {
+    OVERRUNS.load(Ordering::Relaxed)
+}
+
+/// Take the next received byte from the first serial interface, if there is one.
+pub fn try_read_byte() -> Option<u8> {
+    interrupts::without_interrupts(|| RECEIVE_QUEUE.lock().pop_front())
+}
+
+/// Wait for a byte on the first serial interface and return it.
+///
+/// The bytes are received by the interrupt handler, so interrupts must be enabled.
+pub fn read_byte() -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Read bytes into `buf` until a newline (`\n` or `\r`) is received or `buf` is full.
+///
+/// Returns the number of bytes written to `buf`, without the newline.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < buf.len() {
+        match read_byte() {
+            b'\n' | b'\r' => break,
+            byte => {
+                buf[len] = byte;
+                len += 1;
+            }
+        }
+    }
+    len
+}
+
+#[test_case]
+fn test_receive_interrupt_loopback() {
+    const MODEM_CONTROL: u16 = 4;
+
+    let mut modem_control: Port<u8> = Port::new(COM1 + MODEM_CONTROL);
+    // send everything back to ourselves
+    unsafe { modem_control.write(0x1b) };
+    interrupts::without_interrupts(|| SERIAL1.lock().send_raw(b'x'));
+
+    let start = crate::interrupts::ticks();
+    let mut byte = None;
+    while byte.is_none() && crate::interrupts::ticks() < start + 10 {
+        byte = try_read_byte();
+    }
+
+    // restore the configuration of `SerialPort::init`
+    unsafe { modem_control.write(0x0b) };
+    assert_eq!(byte, Some(b'x'));
+}
+
+#[test_case]
+fn test_println_tees_to_serial() {
+    const MODEM_CONTROL: u16 = 4;
+
+    let mut modem_control: Port<u8> = Port::new(COM1 + MODEM_CONTROL);
+    // send everything back to ourselves
+    unsafe { modem_control.write(0x1b) };
+    interrupts::without_interrupts(|| {
+        crate::vga_buffer::set_tee_serial(true);
+        crate::println!("tee");
+        crate::vga_buffer::set_tee_serial(false);
+    });
+
+    let mut received: heapless::Vec<u8, RECEIVE_QUEUE_SIZE> = heapless::Vec::new();
+    let start = crate::interrupts::ticks();
+    while !received.ends_with(b"\n") && crate::interrupts::ticks() < start + 10 {
+        if let Some(byte) = try_read_byte() {
+            let _ = received.push(byte);
+        }
+    }
+
+    // restore the configuration of `SerialPort::init`
+    unsafe { modem_control.write(0x0b) };
+    assert_eq!(received.as_slice(), b"tee\n");
+}
+
+#[test_case]
+fn test_serial_try_println_does_not_wait() {
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock();
+        // would spin forever with `serial_println!`
+        crate::serial_try_println!("test_serial_try_println_does_not_wait output");
+        assert!(!try_print(format_args!("dropped")));
+    });
+}
+
+#[test_case]
+fn test_assert_serial_contains() {
+    start_capture();
+    crate::serial_println!("test_assert_serial_contains output {}", 42);
+    stop_capture();
+    crate::serial_println!("not captured");
+    crate::assert_serial_contains!("output 42\n");
+    assert!(!captured_contains("not captured"));
+}
+
+#[test_case]
+fn test_print_to_serial2() {
+    // COM1 is the test runner's log, only COM2 gets the output
+    serial2_println!("test_print_to_serial2 output");
+}
@@ -0,0 +1,168 @@
+use crate::task::keyboard::{self, ScancodeStream};
+use crate::{allocator, clear_screen, cpu_usage, interrupts, klog, print, println, time, util};
+use alloc::vec::Vec;
+use futures_util::stream::StreamExt;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+
+/// A built-in command, the name and the handler that gets the arguments after the name
+pub type Command = (&'static str, fn(&[&str]));
+
+/// The commands `run` knows about
+pub const COMMANDS: &[Command] = &[
+    ("clear", clear),
+    ("dmesg", dmesg),
+    ("echo", echo),
+    ("help", help),
+    ("irqstats", irqstats),
+    ("meminfo", meminfo),
+    ("uptime", uptime),
+];
+
+const PROMPT: &str = "> ";
+
+/// Read lines from the keyboard and execute them, forever.
+///
+/// Takes over the keyboard, so it can not run together with `print_keypresses`,
+/// whichever comes second returns right away.
+pub async fn run() {
+    let Some(mut scancodes) = ScancodeStream::new() else {
+        println!("WARNING: keyboard already in use; no shell");
+        return;
+    };
+    let mut keyboard = Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    );
+
+    print!("{}", PROMPT);
+    while let Some(scancode) = scancodes.next().await {
+        let key = match keyboard.add_byte(scancode) {
+            Ok(Some(key_event)) => {
+                keyboard::track_modifiers(&key_event);
+                keyboard.process_keyevent(key_event)
+            }
+            _ => None,
+        };
+        // the line buffer echoes the input and handles backspace
+        if let Some(DecodedKey::Unicode(character)) = key {
+            keyboard::add_char(character);
+        }
+        while let Some(line) = keyboard::read_line() {
+            if !dispatch(&line, COMMANDS) {
+                println!(
+                    "unknown command: {}",
+                    line.split_whitespace().next().unwrap_or("")
+                );
+            }
+            print!("{}", PROMPT);
+        }
+    }
+}
+
+/// Split `line` at whitespace and call the handler of the command named by the first word.
+///
+/// Returns false if there is no command with that name, an empty line does nothing.
+pub fn dispatch(line: &str, commands: &[Command]) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (name, args),
+        None => return true,
+    };
+    match commands.iter().find(|(command, _)| command == name) {
+        Some((_, handler)) => {
+            handler(args);
+            true
+        }
+        None => false,
+    }
+}
+
+fn clear(_args: &[&str]) {
+    clear_screen!();
+}
+
+fn dmesg(_args: &[&str]) {
+    klog::dump_klog();
+}
+
+fn echo(args: &[&str]) {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            print!(" ");
+        }
+        print!("{}", arg);
+    }
+    println!();
+}
+
+fn help(_args: &[&str]) {
+    for (name, _) in COMMANDS {
+        println!("  {}", name);
+    }
+}
+
+fn irqstats(_args: &[&str]) {
+    interrupts::print_interrupt_stats();
+}
+
+fn meminfo(_args: &[&str]) {
+    println!(
+        "heap: {} at {:#x}",
+        util::format_bytes(allocator::heap_size() as u64),
+        allocator::HEAP_START
+    );
+}
+
+fn uptime(_args: &[&str]) {
+    let ms = time::uptime_ms();
+    println!(
+        "up {}.{:03} s, cpu {}%",
+        ms / 1000,
+        ms % 1000,
+        cpu_usage::cpu_usage_percent()
+    );
+}
+
+#[test_case]
+fn test_dispatch_calls_handler_with_args() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static FIRST_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static SECOND_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn first(args: &[&str]) {
+        assert_eq!(args, ["a", "bc"]);
+        FIRST_CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+    fn second(args: &[&str]) {
+        assert!(args.is_empty());
+        SECOND_CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+    let commands: &[Command] = &[("first", first), ("second", second)];
+
+    assert!(dispatch("  first a   bc ", commands));
+    assert!(dispatch("second", commands));
+    assert_eq!(FIRST_CALLS.load(Ordering::SeqCst), 1);
+    assert_eq!(SECOND_CALLS.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn test_dispatch_unknown_command() {
+    fn never(_args: &[&str]) {
+        panic!("wrong handler called");
+    }
+    let commands: &[Command] = &[("known", never)];
+
+    assert!(!dispatch("unknown known", commands));
+    assert!(!dispatch("know", commands));
+    // an empty line is not an error
+    assert!(dispatch("   ", commands));
+}
+
+#[test_case]
+fn test_builtin_commands() {
+    for line in ["echo builtin commands output", "uptime", "meminfo", "help"] {
+        assert!(dispatch(line, COMMANDS));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::acpi::{self, SdtHeader};
+use crate::msr::{self, EFER_LONG_MODE_ENABLE, EFER_NO_EXECUTE_ENABLE};
+use crate::{apic, memory, time};
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Physical address the real mode trampoline is copied to.
+///
+/// It has to be page aligned and below 1 MiB for the startup IPI. The page is part
+/// of the bootloader's own code, which is not used anymore once the kernel runs.
+const TRAMPOLINE_ADDRESS: u64 = 0x8000;
+// the startup IPI takes the page number of the trampoline
+const TRAMPOLINE_PAGE: u8 = (TRAMPOLINE_ADDRESS / 4096) as u8;
+
+/// Pages of the stack each application processor gets
+const AP_STACK_PAGES: usize = 16;
+
+// how long an AP has to report alive after a startup IPI
+const STARTUP_TIMEOUT_MS: u64 = 100;
+// wait after the INIT IPI, before the first startup IPI
+const INIT_DELAY_MS: u64 = 10;
+
+// the MADT has the local APIC address and flags (both u32) before its entries
+const MADT_ENTRIES_OFFSET: usize = mem::size_of::<SdtHeader>() + 8;
+const MADT_LOCAL_APIC: u8 = 0;
+// the processor can be used, otherwise it is disabled by the firmware
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+// application processors that reached `ap_main`
+static APS_ALIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Entry of the MADT that describes one processor
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct MadtLocalApic {
+    entry_type: u8,
+    length: u8,
+    processor_id: u8,
+    apic_id: u8,
+    flags: u32,
+}
+
+/// Filled in by `start_aps` before each AP is started, at the end of the trampoline
+#[repr(C)]
+struct TrampolineData {
+    // only the low 32 bits are loaded, the trampoline sets CR3 in real mode
+    page_table: u64,
+    efer: u64,
+    stack_top: u64,
+    entry: u64,
+}
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// The local APIC is needed to send the startup IPIs, see `apic::init`
+    ApicDisabled,
+    /// The level 4 page table is above 4 GiB, so the trampoline can not load it
+    PageTableTooHigh,
+    /// Mapping the trampoline or an AP stack failed
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        SmpError::Map(error)
+    }
+}
+
+// The startup IPI starts an AP in real mode at `TRAMPOLINE_ADDRESS`. It loads
+// a GDT with a 64 bit code segment, the kernel's page tables and goes to long
+// mode in one step, then calls `TrampolineData::entry` on its own stack. The
+// code is copied, so everything it addresses is relative to the start.
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_data",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    // cs is the segment of the trampoline, data is addressed through it
+    "mov ax, cs",
+    "mov ds, ax",
+    "lgdt [ap_gdt_pointer_offset]",
+    // PAE paging
+    "mov eax, cr4",
+    "or eax, 1 << 5",
+    "mov cr4, eax",
+    "mov eax, [ap_trampoline_data_offset]",
+    "mov cr3, eax",
+    "mov ecx, {efer}",
+    "rdmsr",
+    "or eax, [ap_trampoline_data_offset + 8]",
+    "wrmsr",
+    // protection and paging at once, without a stop in protected mode
+    "mov eax, cr0",
+    "or eax, 0x80000001",
+    "mov cr0, eax",
+    // far jump with a 32 bit offset to the 64 bit code segment
+    ".byte 0x66, 0xea",
+    ".long {base} + ap_long_mode - ap_trampoline_start",
+    ".word 8",
+    ".code64",
+    "ap_long_mode:",
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, [rip + ap_trampoline_data + 16]",
+    // the stack top is 16 byte aligned, so the entry sees the usual alignment
+    "call [rip + ap_trampoline_data + 24]",
+    "ud2",
+    ".balign 8",
+    "ap_gdt:",
+    ".quad 0",
+    // present, ring 0, executable, 64 bit
+    ".quad 0x00209a0000000000",
+    // present, ring 0, writable
+    ".quad 0x0000920000000000",
+    "ap_gdt_pointer:",
+    ".word ap_gdt_pointer - ap_gdt - 1",
+    ".long {base} + ap_gdt - ap_trampoline_start",
+    ".balign 8",
+    "ap_trampoline_data:",
+    ".quad 0, 0, 0, 0",
+    "ap_trampoline_end:",
+    ".set ap_gdt_pointer_offset, ap_gdt_pointer - ap_trampoline_start",
+    ".set ap_trampoline_data_offset, ap_trampoline_data - ap_trampoline_start",
+    ".popsection",
+    base = const TRAMPOLINE_ADDRESS,
+    efer = const msr::IA32_EFER.0,
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: TrampolineData;
+    static ap_trampoline_end: u8;
+}
+
+/// APIC ids of the enabled processors in the MADT, empty without ACPI tables
+fn local_apic_ids() -> impl Iterator<Item = u8> {
+    let madt = acpi::find_table(acpi::MADT);
+    let length = madt.map_or(
+        0,
+        |madt| unsafe { acpi::table_header(madt) }.length as usize,
+    );
+    let mut offset = MADT_ENTRIES_OFFSET;
+    core::iter::from_fn(move || {
+        let madt = madt?;
+        // every entry starts with its type and length
+        while offset + 2 <= length {
+            let entry = madt + offset;
+            let [entry_type, entry_length]: [u8; 2] = unsafe { acpi::read_phys(entry) };
+            if entry_length < 2 {
+                // a broken table, stop instead of looping forever
+                return None;
+            }
+            offset += usize::from(entry_length);
+            if entry_type == MADT_LOCAL_APIC
+                && usize::from(entry_length) >= mem::size_of::<MadtLocalApic>()
+            {
+                let local_apic: MadtLocalApic = unsafe { acpi::read_phys(entry) };
+                if local_apic.flags & LOCAL_APIC_ENABLED != 0 {
+                    return Some(local_apic.apic_id);
+                }
+            }
+        }
+        None
+    })
+}
+
+/// Number of usable processors in the ACPI MADT, including the bootstrap processor.
+///
+/// Returns 1 if `acpi::init` did not find the tables.
+pub fn cpu_count() -> usize {
+    local_apic_ids().count().max(1)
+}
+
+/// Number of application processors started by `start_aps` that are running
+pub fn aps_alive() -> usize {
+    APS_ALIVE.load(Ordering::SeqCst)
+}
+
+/// Start all other processors of the MADT with INIT-SIPI-SIPI, one after the other.
+///
+/// Each AP gets its own stack and halts in `hlt_loop` with interrupts disabled,
+/// nothing is scheduled on it yet. Needs `acpi::init` and `apic::init`, and
+/// interrupts enabled for the timeouts. Returns the number of APs that reported
+/// alive, the ones that did not are logged and skipped.
+pub fn start_aps(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<usize, SmpError> {
+    if !apic::is_enabled() {
+        return Err(SmpError::ApicDisabled);
+    }
+    let (level_4_table, _) = Cr3::read();
+    let page_table = level_4_table.start_address().as_u64();
+    if page_table > u64::from(u32::MAX) {
+        return Err(SmpError::PageTableTooHigh);
+    }
+
+    // the instructions right after paging is turned on run at the physical address
+    let frame = PhysFrame::containing_address(PhysAddr::new(TRAMPOLINE_ADDRESS));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.identity_map(frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        // the bootloader identity maps the page with its context switch code
+        Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(error) => return Err(error.into()),
+    }
+    let trampoline = mapper.phys_offset() + TRAMPOLINE_ADDRESS;
+    let data = unsafe { copy_trampoline(trampoline) };
+
+    let mut efer = EFER_LONG_MODE_ENABLE;
+    if memory::no_execute_enabled() {
+        // the kernel's page tables use the no-execute bit
+        efer |= EFER_NO_EXECUTE_ENABLE;
+    }
+    let bsp = apic::local_apic_id();
+    let mut started = 0;
+    for apic_id in local_apic_ids().filter(|&apic_id| apic_id != bsp) {
+        let stack = memory::allocate_stack(AP_STACK_PAGES, mapper, frame_allocator)?;
+        unsafe {
+            data.write_volatile(TrampolineData {
+                page_table,
+                efer,
+                stack_top: stack.end().as_u64(),
+                entry: VirtAddr::from_ptr(ap_main as *const ()).as_u64(),
+            })
+        };
+        if start_ap(apic_id, started + 1) {
+            started += 1;
+        } else {
+            log::warn!("CPU with APIC id {} did not start", apic_id);
+        }
+    }
+    Ok(started)
+}
+
+/// Copy the trampoline to `destination` and return a pointer to its data there.
+///
+/// This function is unsafe because `destination` must be the mapping of
+/// `TRAMPOLINE_ADDRESS` and the page must not be used for anything else.
+unsafe fn copy_trampoline(destination: VirtAddr) -> *mut TrampolineData {
+    let start = core::ptr::addr_of!(ap_trampoline_start);
+    let len = core::ptr::addr_of!(ap_trampoline_end) as usize - start as usize;
+    let data_offset = core::ptr::addr_of!(ap_trampoline_data) as usize - start as usize;
+    let destination = destination.as_mut_ptr::<u8>();
+    core::ptr::copy_nonoverlapping(start, destination, len);
+    destination.add(data_offset).cast()
+}
+
+/// Send INIT-SIPI-SIPI to `apic_id` and wait until `APS_ALIVE` reaches `alive`
+fn start_ap(apic_id: u8, alive: usize) -> bool {
+    apic::send_init_ipi(apic_id);
+    time::sleep_ms(INIT_DELAY_MS);
+    // the second startup IPI is only needed when the first one got lost
+    for _ in 0..2 {
+        apic::send_startup_ipi(apic_id, TRAMPOLINE_PAGE);
+        if wait_until_alive(alive, STARTUP_TIMEOUT_MS) {
+            return true;
+        }
+    }
+    false
+}
+
+fn wait_until_alive(alive: usize, timeout_ms: u64) -> bool {
+    let deadline = time::uptime_ms() + timeout_ms;
+    while APS_ALIVE.load(Ordering::SeqCst) < alive {
+        if time::uptime_ms() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// Where the trampoline of an AP ends up, in long mode on its own stack
+extern "C" fn ap_main() -> ! {
+    APS_ALIVE.fetch_add(1, Ordering::SeqCst);
+    // no IDT is loaded on this CPU, so interrupts stay disabled
+    crate::hlt_loop();
+}
@@ -0,0 +1,77 @@
+use crate::println;
+use bootloader::BootInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+/// Size of the kernel stack in pages, the bootloader's default for `kernel-stack-size`
+pub const KERNEL_STACK_PAGES: u64 = 512;
+
+/// Value written to the lowest word of the kernel stack
+const CANARY: u64 = 0x57ac_c0de_57ac_c0de;
+
+// address of the canary, 0 until `init` placed it
+static CANARY_ADDR: AtomicU64 = AtomicU64::new(0);
+
+/// Lowest address of the kernel stack.
+///
+/// This relies on the default layout of bootloader 0.9.23: without a
+/// `kernel-stack-address` in the manifest it maps the stack right after the boot
+/// info page, with the first page left unmapped as a guard. Other versions or
+/// settings can put the stack somewhere else, `init` checks it against the stack
+/// pointer.
+pub fn kernel_stack_bottom(boot_info: &'static BootInfo) -> VirtAddr {
+    let boot_info_page = VirtAddr::from_ptr(boot_info).align_down(4096u64);
+    boot_info_page + 2 * 4096u64
+}
+
+/// Place the canary at the low end of the kernel stack.
+///
+/// Without a canary if the stack pointer is not in the stack `kernel_stack_bottom`
+/// expects, the address may not be mapped or belong to something else then.
+pub fn init(boot_info: &'static BootInfo) {
+    let bottom = kernel_stack_bottom(boot_info);
+    let top = bottom + KERNEL_STACK_PAGES * 4096;
+    let local = 0u8;
+    let stack_pointer = VirtAddr::from_ptr(&local);
+    if !(bottom..top).contains(&stack_pointer) {
+        println!(
+            "WARNING: kernel stack is not at {:?}, no stack canary",
+            bottom
+        );
+        return;
+    }
+    let canary = bottom.as_mut_ptr::<u64>();
+    // nothing lives down there until the stack overflows
+    unsafe { canary.write_volatile(CANARY) };
+    CANARY_ADDR.store(canary as u64, Ordering::Relaxed);
+}
+
+fn canary_intact() -> bool {
+    match CANARY_ADDR.load(Ordering::Relaxed) {
+        0 => true,
+        addr => unsafe { (addr as *const u64).read_volatile() == CANARY },
+    }
+}
+
+/// Panic if the kernel stack grew into its lowest word, called by the timer handler
+pub fn check_stack_canary() {
+    if !canary_intact() {
+        panic!("stack overflow detected");
+    }
+}
+
+#[test_case]
+fn test_clobbered_canary_is_detected() {
+    let addr = CANARY_ADDR.load(Ordering::Relaxed);
+    assert_ne!(addr, 0, "canary was not placed");
+    let canary = addr as *mut u64;
+    // simulate a stack overflow, with interrupts off so the timer doesn't panic
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        canary.write_volatile(0);
+        let tripped = !canary_intact();
+        canary.write_volatile(CANARY);
+        assert!(tripped);
+    });
+    assert!(canary_intact());
+    check_stack_canary();
+}
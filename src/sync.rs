@@ -0,0 +1,163 @@
+use core::arch::x86_64::_rdtsc;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// The mutex used for the global `WRITER` and `SERIAL1`, a `DebugMutex` with the `debug-locks` feature
+#[cfg(feature = "debug-locks")]
+pub type Mutex<T> = DebugMutex<T>;
+#[cfg(feature = "debug-locks")]
+pub type MutexGuard<'a, T> = DebugMutexGuard<'a, T>;
+#[cfg(not(feature = "debug-locks"))]
+pub type Mutex<T> = spin::Mutex<T>;
+#[cfg(not(feature = "debug-locks"))]
+pub type MutexGuard<'a, T> = spin::MutexGuard<'a, T>;
+
+/// TSC cycles a `lock` may spin before it is reported as a deadlock, around a second.
+///
+/// Timer ticks would not work here, the global locks are taken with interrupts disabled.
+const DEADLOCK_CYCLES: u64 = 1 << 31;
+
+/// A spin lock that panics instead of hanging when it looks like it deadlocked.
+///
+/// It remembers where it was locked and whether that was in an interrupt handler,
+/// so the panic message tells who holds it.
+pub struct DebugMutex<T> {
+    name: &'static str,
+    // `Location` of the `lock` call that holds the lock, null if unlocked
+    holder: AtomicPtr<Location<'static>>,
+    // whether the holder locked it in an interrupt handler, only valid while `holder` is set
+    holder_in_interrupt: AtomicBool,
+    inner: spin::Mutex<T>,
+}
+
+/// Unlocks the `DebugMutex` when dropped
+pub struct DebugMutexGuard<'a, T> {
+    holder: &'a AtomicPtr<Location<'static>>,
+    guard: spin::MutexGuard<'a, T>,
+}
+
+impl<T> DebugMutex<T> {
+    /// Create a lock named after the type it protects
+    pub fn new(value: T) -> Self {
+        DebugMutex::named(core::any::type_name::<T>(), value)
+    }
+
+    /// Create a lock with the given name for the deadlock message
+    pub const fn named(name: &'static str, value: T) -> Self {
+        DebugMutex {
+            name,
+            holder: AtomicPtr::new(ptr::null_mut()),
+            holder_in_interrupt: AtomicBool::new(false),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    /// Spin until the lock is free, panics if that takes longer than `DEADLOCK_CYCLES`
+    #[track_caller]
+    pub fn lock(&self) -> DebugMutexGuard<'_, T> {
+        let caller = Location::caller();
+        let start = unsafe { _rdtsc() };
+        loop {
+            if let Some(guard) = self.try_lock_at(caller) {
+                return guard;
+            }
+            let now = unsafe { _rdtsc() };
+            if now.wrapping_sub(start) > DEADLOCK_CYCLES {
+                self.report_deadlock(caller);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<DebugMutexGuard<'_, T>> {
+        self.try_lock_at(Location::caller())
+    }
+
+    fn try_lock_at(&self, caller: &'static Location<'static>) -> Option<DebugMutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        self.holder_in_interrupt
+            .store(crate::interrupts::in_interrupt_handler(), Ordering::SeqCst);
+        self.holder
+            .store(caller as *const _ as *mut _, Ordering::SeqCst);
+        Some(DebugMutexGuard {
+            holder: &self.holder,
+            guard,
+        })
+    }
+
+    fn report_deadlock(&self, caller: &'static Location<'static>) -> ! {
+        let holder = self.holder.load(Ordering::SeqCst);
+        let context = if self.holder_in_interrupt.load(Ordering::SeqCst) {
+            " in an interrupt handler"
+        } else {
+            ""
+        };
+        // this could be the lock of SERIAL1, so do not print through it
+        match unsafe { holder.as_ref() } {
+            Some(holder) => crate::serial::write_raw(format_args!(
+                "possible deadlock on {}: locked at {}, held since {}{}\n",
+                self.name, caller, holder, context
+            )),
+            None => crate::serial::write_raw(format_args!(
+                "possible deadlock on {}: locked at {}\n",
+                self.name, caller
+            )),
+        }
+        // the holder never continues while this CPU spins, so free the lock for the
+        // panic handler, which prints the backtrace of the waiting side
+        unsafe { self.force_unlock() };
+        panic!("possible deadlock on {}, see the report above", self.name);
+    }
+
+    /// Force the lock to be unlocked, e.g. to print from a panic handler
+    ///
+    /// This is unsafe because the current holder may still be using the value.
+    pub unsafe fn force_unlock(&self) {
+        self.holder.store(ptr::null_mut(), Ordering::SeqCst);
+        self.inner.force_unlock();
+    }
+}
+
+impl<T> Deref for DebugMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for DebugMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for DebugMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // `guard` is dropped after this, which does the actual unlock
+        self.holder.store(ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn test_lock_and_unlock() {
+    let mutex = DebugMutex::named("test_lock_and_unlock", 1);
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+    }
+    assert_eq!(*mutex.lock(), 2);
+}
+
+crate::should_panic_test! {
+    fn test_relock_is_detected() {
+        let mutex = DebugMutex::named("test_relock_is_detected", ());
+        let _first = mutex.lock();
+        // there is only one CPU, so this never succeeds
+        let _second = mutex.lock();
+    }
+}
@@ -0,0 +1,93 @@
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub mod executor;
+pub mod keyboard;
+pub mod simple_executor;
+pub mod timer;
+
+/// A unit of cooperative work: a heap allocated future that produces no value
+pub struct Task {
+    id: TaskId,
+    // pinned because futures may hold references to themselves once polled
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// Unique id of a task, used by wakers to tell the executor which task to poll
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Future returned by `yield_now`
+pub struct YieldNow {
+    yielded: bool,
+}
+
+/// Hand control back to the executor so other ready tasks can run.
+///
+/// The task is woken right away, so it is polled again after the tasks queued before it.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn test_yield_now_interleaves_tasks() {
+    use alloc::vec::Vec;
+    use executor::Executor;
+    use spin::Mutex;
+
+    static LOG: Mutex<Vec<(char, u32)>> = Mutex::new(Vec::new());
+
+    async fn worker(name: char) {
+        for step in 0..3 {
+            LOG.lock().push((name, step));
+            yield_now().await;
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(worker('a')));
+    executor.spawn(Task::new(worker('b')));
+    executor.run_until_complete();
+
+    let log = core::mem::take(&mut *LOG.lock());
+    assert_eq!(
+        log,
+        [('a', 0), ('b', 0), ('a', 1), ('b', 1), ('a', 2), ('b', 2)]
+    );
+}
@@ -0,0 +1,168 @@
+use super::{Task, TaskId};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+
+/// Number of task ids that can be waiting in the ready queue at the same time
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// An executor that only polls a task again after it has been woken.
+///
+/// When no task is ready the CPU is halted until the next interrupt.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    // shared with the wakers, which push the id of the task they wake
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    // reuse the waker of a task instead of allocating a new one on every poll
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    /// Run the spawned tasks forever
+    pub fn run(&mut self) -> ! {
+        loop {
+            // every timer interrupt wakes the loop up, so this keeps kicking while the kernel is healthy
+            crate::watchdog::kick();
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Run the spawned tasks, returns once all of them are done
+    pub fn run_until_complete(&mut self) {
+        while !self.tasks.is_empty() {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to borrow the fields separately inside the loop
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Some(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+        // an interrupt handler could wake a task right after the check, so
+        // the check and the `hlt` must happen with interrupts disabled,
+        // `enable_and_hlt` turns them back on atomically with halting
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            crate::cpu_usage::set_idle(true);
+            enable_and_hlt();
+            crate::cpu_usage::set_idle(false);
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+        }))
+    }
+
+    fn wake_task(&self) {
+        // wakers are called from interrupt handlers, so drop the wakeup instead of panicking
+        if self.task_queue.push(self.task_id).is_err() {
+            crate::serial_try_println!(
+                "WARNING: task queue full; dropping wakeup of {:?}",
+                self.task_id
+            );
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[test_case]
+fn test_wake_on_timer_tick() {
+    use super::timer;
+    use alloc::boxed::Box;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    /// Counts how often the executor polls the wrapped future
+    struct CountPolls(Pin<Box<dyn Future<Output = ()>>>);
+
+    impl Future for CountPolls {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            POLLS.fetch_add(1, Ordering::SeqCst);
+            self.0.as_mut().poll(context)
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(CountPolls(Box::pin(async {
+        timer::next_tick().await;
+        DONE.store(true, Ordering::SeqCst);
+    }))));
+    executor.run_until_complete();
+    assert!(DONE.load(Ordering::SeqCst));
+    // polled once to park and once more after the tick woke it up
+    assert!(POLLS.load(Ordering::SeqCst) <= 2);
+}
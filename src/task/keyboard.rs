@@ -0,0 +1,453 @@
+use crate::sync::Mutex;
+use crate::{interrupts, print, println};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet1,
+};
+use x86_64::instructions::port::Port;
+
+/// Number of scancodes that can be buffered before the keyboard handler drops input
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+// allocated by `ScancodeStream::new`, the interrupt handler must not allocate
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+// woken when a new scancode is pushed
+static WAKER: AtomicWaker = AtomicWaker::new();
+// only warn about a full queue once instead of flooding the screen
+static QUEUE_FULL_WARNED: AtomicBool = AtomicBool::new(false);
+// set while a `ScancodeStream` exists, two of them would steal each other's scancodes
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Called by the keyboard interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_scancode(scancode: u8) {
+    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
+        if queue.push(scancode).is_err() {
+            if !QUEUE_FULL_WARNED.swap(true, Ordering::Relaxed) {
+                println!("WARNING: scancode queue full; dropping keyboard input");
+            }
+        } else {
+            WAKER.wake();
+        }
+    } else {
+        println!("WARNING: scancode queue uninitialized");
+    }
+}
+
+/// Stream of the raw scancodes received by the keyboard interrupt handler
+pub struct ScancodeStream {
+    // makes sure the stream can only be created through `new`
+    _private: (),
+}
+
+impl ScancodeStream {
+    /// Create the scancode stream, `None` while another one exists.
+    ///
+    /// Once that one is dropped a new stream can be created, it gets the scancodes
+    /// that were not read yet.
+    pub fn new() -> Option<Self> {
+        if STREAM_TAKEN.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        // the queue stays allocated, the next stream reuses it
+        let _ = SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE));
+        Some(ScancodeStream { _private: () })
+    }
+}
+
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        STREAM_TAKEN.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        // fast path, no need to register the waker
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(context.waker());
+        // a scancode could have been pushed before the waker was registered
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+// I/O ports of the PS/2 controller
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+// status register bits: a byte can be read / the controller has not taken the last written byte yet
+const OUTPUT_BUFFER_FULL: u8 = 1;
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+// keyboard command to set the LEDs, followed by a byte of `Modifiers::led_bits`
+const SET_LEDS: u8 = 0xED;
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+/// How often a byte is sent again when the keyboard asks for a resend
+const MAX_RETRIES: usize = 3;
+// status polls before giving up on the controller
+const WAIT_SPINS: usize = 100_000;
+
+/// Modifier keys that are held down and lock keys that are on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+    // lock keys that are held down, a held key repeats its key down event
+    held_locks: u8,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Update the state with a key event, returns whether a lock key was toggled
+    pub fn update(&mut self, event: &KeyEvent) -> bool {
+        let down = match event.state {
+            KeyState::Down => true,
+            KeyState::Up => false,
+            KeyState::SingleShot => return false,
+        };
+        match event.code {
+            KeyCode::LShift => self.left_shift = down,
+            KeyCode::RShift => self.right_shift = down,
+            KeyCode::LControl => self.left_ctrl = down,
+            KeyCode::RControl => self.right_ctrl = down,
+            KeyCode::LAlt => self.alt = down,
+            KeyCode::RAltGr => self.alt_gr = down,
+            KeyCode::CapsLock => return self.press_lock(0, down, |m| &mut m.caps_lock),
+            KeyCode::NumpadLock => return self.press_lock(1, down, |m| &mut m.num_lock),
+            KeyCode::ScrollLock => return self.press_lock(2, down, |m| &mut m.scroll_lock),
+            _ => {}
+        }
+        false
+    }
+
+    // toggle the lock on the first key down event only, until the key is released
+    fn press_lock(&mut self, bit: u8, down: bool, lock: fn(&mut Self) -> &mut bool) -> bool {
+        let was_held = self.held_locks & (1 << bit) != 0;
+        if down {
+            self.held_locks |= 1 << bit;
+        } else {
+            self.held_locks &= !(1 << bit);
+        }
+        if down && !was_held {
+            let lock = lock(self);
+            *lock = !*lock;
+            return true;
+        }
+        false
+    }
+
+    /// The byte that follows the set LEDs command
+    pub fn led_bits(&self) -> u8 {
+        u8::from(self.scroll_lock) | u8::from(self.num_lock) << 1 | u8::from(self.caps_lock) << 2
+    }
+}
+
+static MODIFIERS: spin::Mutex<Modifiers> = spin::Mutex::new(Modifiers {
+    left_shift: false,
+    right_shift: false,
+    left_ctrl: false,
+    right_ctrl: false,
+    alt: false,
+    alt_gr: false,
+    caps_lock: false,
+    num_lock: false,
+    scroll_lock: false,
+    held_locks: 0,
+});
+
+/// The current state of the modifier and lock keys
+pub fn modifiers() -> Modifiers {
+    *MODIFIERS.lock()
+}
+
+/// Track the modifier keys, called with every key event before it is decoded.
+///
+/// Switches the keyboard LEDs when a lock key is toggled.
+pub fn track_modifiers(event: &KeyEvent) {
+    let (toggled, state) = {
+        let mut modifiers = MODIFIERS.lock();
+        (modifiers.update(event), *modifiers)
+    };
+    if toggled {
+        if let Err(error) = set_leds(&state) {
+            log::warn!("could not set the keyboard LEDs: {:?}", error);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardError {
+    /// The controller did not take a byte or the keyboard did not answer in time
+    Timeout,
+    /// The keyboard still asked for a resend after `MAX_RETRIES` tries
+    Resend,
+}
+
+/// Turn the keyboard LEDs on or off to match the lock keys in `modifiers`
+pub fn set_leds(modifiers: &Modifiers) -> Result<(), KeyboardError> {
+    // the keyboard interrupt handler must not take the answers as scancodes
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        send_to_keyboard(SET_LEDS)?;
+        send_to_keyboard(modifiers.led_bits())
+    })
+}
+
+// write `byte` to the keyboard and wait for the ACK, sending it again on a resend request
+fn send_to_keyboard(byte: u8) -> Result<(), KeyboardError> {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..MAX_RETRIES {
+        wait_for_status(&mut status, INPUT_BUFFER_FULL, false)?;
+        unsafe { data.write(byte) };
+        loop {
+            wait_for_status(&mut status, OUTPUT_BUFFER_FULL, true)?;
+            match unsafe { data.read() } {
+                ACK => return Ok(()),
+                RESEND => break,
+                // a key was pressed in between, keep it
+                scancode => add_scancode(scancode),
+            }
+        }
+    }
+    Err(KeyboardError::Resend)
+}
+
+fn wait_for_status(status: &mut Port<u8>, bit: u8, set: bool) -> Result<(), KeyboardError> {
+    for _ in 0..WAIT_SPINS {
+        if (unsafe { status.read() } & bit != 0) == set {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(KeyboardError::Timeout)
+}
+
+/// Characters an input line can hold, see `add_char`
+pub const LINE_BUFFER_SIZE: usize = 128;
+
+/// A line of keyboard input that is edited until Enter completes it.
+///
+/// Typed characters are echoed to the screen. Once the line is full, further input
+/// is ignored until Enter.
+pub struct LineBuffer<const N: usize> {
+    line: heapless::String<N>,
+    // set when a character did not fit, everything up to Enter is dropped
+    full: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            line: heapless::String::new(),
+            full: false,
+        }
+    }
+
+    /// Add a decoded character, returns the line once `character` is Enter
+    pub fn push(&mut self, character: char) -> Option<String> {
+        match character {
+            '\n' => {
+                println!();
+                let line = String::from(self.line.as_str());
+                self.line.clear();
+                self.full = false;
+                return Some(line);
+            }
+            _ if self.full => {}
+            // backspace
+            '\u{8}' => {
+                if self.line.pop().is_some() {
+                    print!("\u{8}");
+                }
+            }
+            _ if character.is_control() => {}
+            _ => match self.line.push(character) {
+                Ok(()) => print!("{}", character),
+                Err(()) => self.full = true,
+            },
+        }
+        None
+    }
+}
+
+lazy_static! {
+    static ref LINE_BUFFER: Mutex<LineBuffer<LINE_BUFFER_SIZE>> = Mutex::new(LineBuffer::new());
+    // lines completed by Enter that `read_line` did not return yet
+    static ref COMPLETED_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+/// Feed a decoded character into the input line, called by the task decoding the scancodes
+pub(crate) fn add_char(character: char) {
+    if let Some(line) = LINE_BUFFER.lock().push(character) {
+        COMPLETED_LINES.lock().push_back(line);
+    }
+}
+
+/// Take the oldest line completed by Enter, `None` if there is none yet
+pub fn read_line() -> Option<String> {
+    COMPLETED_LINES.lock().pop_front()
+}
+
+/// Decode the scancodes received from the keyboard and print the typed keys
+pub async fn print_keypresses() {
+    let Some(mut scancodes) = ScancodeStream::new() else {
+        println!("WARNING: keyboard already in use; not printing keypresses");
+        return;
+    };
+    let mut keyboard = Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    );
+
+    while let Some(scancode) = scancodes.next().await {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            track_modifiers(&key_event);
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                match key {
+                    DecodedKey::Unicode(character) => {
+                        interrupts::record_key(character);
+                        print!("{}", character)
+                    }
+                    DecodedKey::RawKey(key) => print!("{:?}", key),
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_scancode_stream_in_order() {
+    use super::executor::Executor;
+    use super::Task;
+    use alloc::vec::Vec;
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    let mut scancodes = ScancodeStream::new().expect("scancode stream in use");
+    for scancode in [0x1e, 0x9e, 0x30] {
+        add_scancode(scancode);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            received.push(scancodes.next().await.unwrap());
+        }
+        assert_eq!(received, [0x1e, 0x9e, 0x30]);
+        DONE.store(true, Ordering::SeqCst);
+    }));
+    executor.run_until_complete();
+    assert!(DONE.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_scancode_stream_is_exclusive() {
+    let scancodes = ScancodeStream::new().expect("scancode stream in use");
+    assert!(ScancodeStream::new().is_none());
+    drop(scancodes);
+    assert!(ScancodeStream::new().is_some());
+}
+
+#[test_case]
+fn test_line_buffer_backspace() {
+    let mut buffer: LineBuffer<16> = LineBuffer::new();
+    for character in "ab\u{8}c".chars() {
+        assert_eq!(buffer.push(character), None);
+    }
+    assert_eq!(buffer.push('\n').as_deref(), Some("ac"));
+    // the buffer starts over after Enter
+    assert_eq!(buffer.push('\n').as_deref(), Some(""));
+}
+
+#[test_case]
+fn test_line_buffer_ignores_input_when_full() {
+    let mut buffer: LineBuffer<4> = LineBuffer::new();
+    for character in "abcdef\u{8}\u{8}".chars() {
+        buffer.push(character);
+    }
+    assert_eq!(buffer.push('\n').as_deref(), Some("abcd"));
+}
+
+#[test_case]
+fn test_read_line() {
+    for character in "first\nsecond\nthird".chars() {
+        add_char(character);
+    }
+    assert_eq!(read_line().as_deref(), Some("first"));
+    assert_eq!(read_line().as_deref(), Some("second"));
+    // not completed by Enter yet
+    assert_eq!(read_line(), None);
+    add_char('\n');
+    assert_eq!(read_line().as_deref(), Some("third"));
+}
+
+#[test_case]
+fn test_caps_lock_toggles_once_per_press() {
+    let mut modifiers = Modifiers::default();
+    let down = KeyEvent::new(KeyCode::CapsLock, KeyState::Down);
+    let up = KeyEvent::new(KeyCode::CapsLock, KeyState::Up);
+
+    assert!(modifiers.update(&down));
+    assert!(modifiers.caps_lock);
+    // holding the key repeats the key down event
+    assert!(!modifiers.update(&down));
+    assert!(!modifiers.update(&up));
+    assert!(modifiers.caps_lock);
+    assert_eq!(modifiers.led_bits(), 0b100);
+
+    assert!(modifiers.update(&down));
+    assert!(!modifiers.caps_lock);
+    assert_eq!(modifiers.led_bits(), 0);
+}
+
+#[test_case]
+fn test_modifier_keys_are_held() {
+    let mut modifiers = Modifiers::default();
+    modifiers.update(&KeyEvent::new(KeyCode::RShift, KeyState::Down));
+    modifiers.update(&KeyEvent::new(KeyCode::LControl, KeyState::Down));
+    assert!(modifiers.shift());
+    assert!(modifiers.ctrl());
+    modifiers.update(&KeyEvent::new(KeyCode::RShift, KeyState::Up));
+    assert!(!modifiers.shift());
+    assert!(modifiers.ctrl());
+}
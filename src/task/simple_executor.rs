@@ -0,0 +1,68 @@
+use super::Task;
+use alloc::collections::VecDeque;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// An executor that keeps polling its tasks in turn until all of them are done.
+///
+/// It has no way to be woken, so a pending task is simply polled again later.
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+}
+
+impl SimpleExecutor {
+    pub fn new() -> SimpleExecutor {
+        SimpleExecutor {
+            task_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task)
+    }
+
+    /// Run the spawned tasks, returns once the queue is empty
+    pub fn run(&mut self) {
+        while let Some(mut task) = self.task_queue.pop_front() {
+            let waker = dummy_waker();
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {} // task done
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null(), vtable)
+}
+
+fn dummy_waker() -> Waker {
+    // the vtable functions never touch the data pointer, so it is fine to be null
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
+
+#[test_case]
+fn test_run_to_completion() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static FIRST_DONE: AtomicBool = AtomicBool::new(false);
+    static SECOND_DONE: AtomicBool = AtomicBool::new(false);
+
+    async fn set_flag(flag: &'static AtomicBool) {
+        flag.store(true, Ordering::SeqCst);
+    }
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(set_flag(&FIRST_DONE)));
+    executor.spawn(Task::new(set_flag(&SECOND_DONE)));
+    executor.run();
+    assert!(FIRST_DONE.load(Ordering::SeqCst));
+    assert!(SECOND_DONE.load(Ordering::SeqCst));
+}
@@ -0,0 +1,79 @@
+use crate::interrupts;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+/// Waker of the task waiting in `next_tick`, woken by the timer interrupt
+static TICK_WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+/// Future that completes once the next timer interrupt has happened
+pub struct NextTick {
+    start: u64,
+}
+
+/// Wait for the next timer interrupt.
+///
+/// Only one task can wait at a time, a later call replaces the earlier waker.
+pub fn next_tick() -> NextTick {
+    NextTick {
+        start: interrupts::ticks(),
+    }
+}
+
+impl Future for NextTick {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if interrupts::ticks() > self.start {
+            clear_waker(context.waker());
+            return Poll::Ready(());
+        }
+        // the timer handler takes the same lock, so keep it from firing while we hold it
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut waker = TICK_WAKER.lock();
+            if !waker
+                .as_ref()
+                .is_some_and(|waker| waker.will_wake(context.waker()))
+            {
+                *waker = Some(context.waker().clone());
+            }
+        });
+        // check again in case the tick happened before the waker was registered
+        if interrupts::ticks() > self.start {
+            clear_waker(context.waker());
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Drop the registered waker if it belongs to `waker`'s task.
+///
+/// The timer interrupt only wakes the registered waker by reference, it is dropped
+/// in task context, here or when `poll` replaces it. Dropping the last reference in
+/// the handler would free the waker there.
+fn clear_waker(waker: &Waker) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut registered = TICK_WAKER.lock();
+        if registered
+            .as_ref()
+            .is_some_and(|registered| registered.will_wake(waker))
+        {
+            *registered = None;
+        }
+    });
+}
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn wake_tick_waiter() {
+    if let Some(waker) = TICK_WAKER.try_lock() {
+        // keep the waker, `clear_waker` drops it once the tick was seen
+        if let Some(waker) = waker.as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::interrupts;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+/// Frequency `init` programs the PIT to, so every timer tick is 10 ms
+pub const DEFAULT_PIT_HZ: u32 = 100;
+
+// input clock of the PIT
+const PIT_BASE_HZ: u32 = 1_193_182;
+// I/O ports of PIT channel 0 and the mode/command register
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// channel 0, access low byte then high byte, mode 3 (square wave), binary
+const PIT_CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+
+// the frequency the PIT actually runs at, the divisor is rounded
+static PIT_HZ: AtomicU32 = AtomicU32::new(DEFAULT_PIT_HZ);
+
+/// The PIT divisor for `hz`, clamped to what the 16 bit counter can hold
+fn pit_divisor(hz: u32) -> u16 {
+    (PIT_BASE_HZ / hz.max(1)).clamp(1, u32::from(u16::MAX)) as u16
+}
+
+/// Program PIT channel 0 to fire at about `hz` times per second.
+///
+/// The PIT runs between 19 Hz and 1.19 MHz, other frequencies are clamped.
+/// Changing it does not rescale the ticks counted so far, so `uptime_ms` jumps.
+/// The APIC timer is calibrated to the frequency at the time of `apic::init`, so
+/// it has to be set before that.
+pub fn set_pit_frequency(hz: u32) {
+    let divisor = pit_divisor(hz);
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut data: Port<u8> = Port::new(PIT_CHANNEL_0);
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        command.write(PIT_CHANNEL_0_SQUARE_WAVE);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+        PIT_HZ.store(PIT_BASE_HZ / u32::from(divisor), Ordering::Relaxed);
+    });
+}
+
+/// Timer ticks per second, as set by `set_pit_frequency`
+pub fn pit_frequency() -> u32 {
+    PIT_HZ.load(Ordering::Relaxed)
+}
+
+/// Milliseconds since the timer was started, in steps of one tick
+pub fn uptime_ms() -> u64 {
+    interrupts::ticks() * 1000 / u64::from(pit_frequency())
+}
+
+/// Halt until at least `ms` milliseconds have passed.
+///
+/// Rounds up to whole ticks and needs interrupts to be enabled.
+pub fn sleep_ms(ms: u64) {
+    let ticks = (ms * u64::from(pit_frequency())).div_ceil(1000);
+    let end = interrupts::ticks() + ticks;
+    while interrupts::ticks() < end {
+        crate::cpu_usage::idle_hlt();
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    sleep_ms(duration.as_millis() as u64);
+}
+
+#[test_case]
+fn test_pit_divisor() {
+    assert_eq!(pit_divisor(100), 11931);
+    assert_eq!(pit_divisor(1000), 1193);
+    // out of the range of the 16 bit counter
+    assert_eq!(pit_divisor(1), u16::MAX);
+    assert_eq!(pit_divisor(0), u16::MAX);
+    assert_eq!(pit_divisor(2_000_000), 1);
+}
+
+#[test_case]
+fn test_sleep_ms_advances_uptime() {
+    let start = uptime_ms();
+    sleep_ms(50);
+    let elapsed = uptime_ms() - start;
+    assert!(elapsed >= 50, "slept only {} ms", elapsed);
+    assert!(elapsed < 100, "slept {} ms", elapsed);
+}
@@ -0,0 +1,143 @@
+use crate::print;
+use core::fmt;
+use core::fmt::Write;
+use heapless::String;
+use x86_64::VirtAddr;
+
+const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+/// Render a byte count with a binary unit and one decimal place, e.g. `1.5 KiB`.
+///
+/// Counts below 1 KiB are printed exactly, like `1023 B`. Does not allocate.
+pub fn format_bytes(n: u64) -> String<16> {
+    let mut out = String::new();
+    if n < 1024 {
+        write!(out, "{} B", n).unwrap();
+        return out;
+    }
+    let mut unit_size: u128 = 1;
+    for (i, unit) in UNITS.iter().enumerate() {
+        unit_size *= 1024;
+        // tenths of the unit, rounded half up
+        let tenths = (n as u128 * 10 + unit_size / 2) / unit_size;
+        // a value that rounds up to 1024.0 is printed in the next unit
+        if tenths < 10240 || i == UNITS.len() - 1 {
+            write!(out, "{}.{} {}", tenths / 10, tenths % 10, unit).unwrap();
+            break;
+        }
+    }
+    out
+}
+
+/// Bytes shown per line of `hexdump`
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Print `len` bytes starting at `addr` as a hexdump, 16 bytes per line with the
+/// address and an ASCII gutter, non-printable bytes are shown as `.`.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the whole range is mapped and readable.
+pub unsafe fn hexdump(addr: VirtAddr, len: usize) {
+    let bytes = core::slice::from_raw_parts(addr.as_ptr::<u8>(), len);
+    // a printer that forwards to `print!`, so the dump needs no buffer
+    struct Screen;
+    impl fmt::Write for Screen {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            print!("{}", s);
+            Ok(())
+        }
+    }
+    write_hexdump(&mut Screen, addr.as_u64(), bytes).unwrap();
+}
+
+/// Write `bytes` as a hexdump to `out`, the first line is labelled with `addr`
+pub fn write_hexdump(out: &mut impl fmt::Write, addr: u64, bytes: &[u8]) -> fmt::Result {
+    for (i, line) in bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+        write!(out, "{:016x} ", addr + (i * HEXDUMP_WIDTH) as u64)?;
+        for column in 0..HEXDUMP_WIDTH {
+            // an extra space between the two halves of the line
+            if column == HEXDUMP_WIDTH / 2 {
+                out.write_char(' ')?;
+            }
+            match line.get(column) {
+                // the memory may be MMIO, so every byte is read exactly once
+                Some(byte) => write!(out, " {:02x}", unsafe {
+                    (byte as *const u8).read_volatile()
+                })?,
+                None => out.write_str("   ")?,
+            }
+        }
+        out.write_str("  |")?;
+        for byte in line {
+            let byte = unsafe { (byte as *const u8).read_volatile() };
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            };
+            out.write_char(c)?;
+        }
+        out.write_str("|\n")?;
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_format_bytes_below_one_kib() {
+    assert_eq!(format_bytes(0).as_str(), "0 B");
+    assert_eq!(format_bytes(1023).as_str(), "1023 B");
+}
+
+#[test_case]
+fn test_format_bytes_kib() {
+    assert_eq!(format_bytes(1024).as_str(), "1.0 KiB");
+    assert_eq!(format_bytes(1536).as_str(), "1.5 KiB");
+    assert_eq!(format_bytes(100 * 1024).as_str(), "100.0 KiB");
+}
+
+#[test_case]
+fn test_format_bytes_unit_boundaries() {
+    // one byte short of 1 MiB rounds up into the next unit
+    assert_eq!(format_bytes(1024 * 1024 - 1).as_str(), "1.0 MiB");
+    assert_eq!(format_bytes(1024 * 1024).as_str(), "1.0 MiB");
+    assert_eq!(format_bytes(1024 * 1024 * 5 / 4).as_str(), "1.3 MiB");
+    assert_eq!(format_bytes(100 * 1024 * 1024).as_str(), "100.0 MiB");
+    assert_eq!(format_bytes(1024 * 1024 * 1024 - 1).as_str(), "1.0 GiB");
+    assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 / 2).as_str(), "1.5 GiB");
+}
+
+#[test_case]
+fn test_format_bytes_largest_value_fits() {
+    assert_eq!(format_bytes(u64::MAX).as_str(), "16777216.0 TiB");
+}
+
+#[test_case]
+fn test_hexdump_format() {
+    use alloc::vec::Vec;
+
+    let buffer: Vec<u8> = (0..20).map(|i| b'A' + i).chain([0, 0x7f, b' ']).collect();
+    let mut out = alloc::string::String::new();
+    write_hexdump(&mut out, 0x4444_4444_0000, &buffer).unwrap();
+
+    let mut lines = out.lines();
+    assert_eq!(
+        lines.next(),
+        Some("0000444444440000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|")
+    );
+    // the last line is padded so the gutter stays aligned
+    assert_eq!(
+        lines.next(),
+        Some("0000444444440010  51 52 53 54 00 7f 20                              |QRST.. |")
+    );
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn test_hexdump_of_heap_buffer() {
+    use alloc::vec;
+
+    let buffer = vec![0xabu8; 32];
+    // only checks that reading through the address works, the output goes to the screen
+    unsafe { hexdump(VirtAddr::from_ptr(buffer.as_ptr()), buffer.len()) };
+}
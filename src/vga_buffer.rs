@@ -1,9 +1,21 @@
+use crate::logger::SinkLevel;
+use crate::sync::Mutex;
+use crate::{allocator, klog, memory, serial};
+use alloc::collections::VecDeque;
 use core::fmt;
 use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use lazy_static::lazy_static;
-use spin::Mutex;
+use log::Level;
 use volatile::Volatile;
 use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
 
 // u4 is enough, but Rust does not support u4
 #[allow(dead_code)]
@@ -28,69 +40,529 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    /// The color with the VGA palette index `n`, if `n` is below 16
+    pub fn from_u8(n: u8) -> Option<Color> {
+        let color = match n {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::DarkGray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            15 => Color::White,
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 // the color byte =
 // 1 bit for blink + 3 bits background color + 4 bits foreground color (include 1 bit for bright)
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    /// The color the writer starts with
+    pub const DEFAULT: ColorCode = ColorCode::on_black(Color::Yellow);
+
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | foreground as u8)
     }
+
+    pub const fn on_black(foreground: Color) -> ColorCode {
+        ColorCode::new(foreground, Color::Black)
+    }
+
+    fn with_foreground(self, foreground: u8) -> ColorCode {
+        ColorCode(self.0 & 0xf0 | foreground & 0x0f)
+    }
+
+    fn with_background(self, background: u8) -> ColorCode {
+        ColorCode((background & 0x0f) << 4 | self.0 & 0x0f)
+    }
+}
+
+impl Default for ColorCode {
+    fn default() -> Self {
+        ColorCode::DEFAULT
+    }
 }
 
+// VGA colors in the order of the ANSI color codes (30-37 and 40-47)
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+// the bright variant of a foreground color has the 4th bit set
+const BRIGHT_BIT: u8 = 0x08;
+
+const ESCAPE: u8 = 0x1b;
+const MAX_ESCAPE_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    // not inside an escape sequence
+    Normal,
+    // got `ESC`
+    Escape,
+    // got `ESC [`, collecting `;` separated parameters until the final byte
+    Csi,
+}
+
+/// Parser state of an `ESC [ ... <letter>` sequence
+struct EscapeSequence {
+    state: EscapeState,
+    params: [u16; MAX_ESCAPE_PARAMS],
+    // index of the parameter that is currently parsed
+    index: usize,
+    // whether SGR 1 (bold) is active, which is shown as bright foreground colors
+    bold: bool,
+}
+
+impl EscapeSequence {
+    const fn new() -> Self {
+        EscapeSequence {
+            state: EscapeState::Normal,
+            params: [0; MAX_ESCAPE_PARAMS],
+            index: 0,
+            bold: false,
+        }
+    }
+}
+
+/// A character cell of the VGA text buffer
 #[repr(C)]
-#[derive(Copy, Clone)]
-struct ScreenChar {
-    ascii_character: u8,
-    color_code: ColorCode,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScreenChar {
+    pub ascii_character: u8,
+    pub color_code: ColorCode,
 }
 
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
+// a `\t` moves to the next column that is a multiple of this
+const TAB_WIDTH: usize = 8;
+
+// the CRTC registers are accessed by writing the register index to the address port
+// and then reading or writing the data port
+const CRTC_ADDRESS_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+
+// how many scrolled-off lines are kept for `scroll_up`
+// each line takes 160 bytes of the heap, so this can not be much larger with a 100 KiB heap
+const SCROLLBACK_LINES: usize = 100;
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+impl Buffer {
+    // `Volatile` is transparent, so the buffer is one contiguous array of cells
+    fn cells(&mut self) -> *mut ScreenChar {
+        self.chars.as_mut_ptr() as *mut ScreenChar
+    }
+}
+
+// four cells fit into a u64, the VGA buffer takes 64 bit writes as well as 16 bit ones
+const CELLS_PER_WORD: usize = 4;
+
+/// Copy `src` to the cells at `dst` with volatile writes, a u64 at a time where `dst` is aligned.
+///
+/// ## Safety
+///
+/// `dst` must be valid for writing `src.len()` cells.
+unsafe fn copy_cells(dst: *mut ScreenChar, src: &[ScreenChar]) {
+    let mut i = 0;
+    while i < src.len() && dst.add(i) as usize % 8 != 0 {
+        dst.add(i).write_volatile(src[i]);
+        i += 1;
+    }
+    while i + CELLS_PER_WORD <= src.len() {
+        // `ScreenChar` is two plain bytes, so four of them can be read as one u64
+        let word = (src.as_ptr().add(i) as *const u64).read_unaligned();
+        (dst.add(i) as *mut u64).write_volatile(word);
+        i += CELLS_PER_WORD;
+    }
+    while i < src.len() {
+        dst.add(i).write_volatile(src[i]);
+        i += 1;
+    }
+}
+
+/// Set `count` cells at `dst` to `value` with volatile writes, a u64 at a time where `dst` is aligned.
+///
+/// ## Safety
+///
+/// `dst` must be valid for writing `count` cells.
+unsafe fn fill_cells(dst: *mut ScreenChar, count: usize, value: ScreenChar) {
+    let cell = u64::from(value.ascii_character) | u64::from(value.color_code.0) << 8;
+    let word = cell * 0x0001_0001_0001_0001;
+    let mut i = 0;
+    while i < count && dst.add(i) as usize % 8 != 0 {
+        dst.add(i).write_volatile(value);
+        i += 1;
+    }
+    while i + CELLS_PER_WORD <= count {
+        (dst.add(i) as *mut u64).write_volatile(word);
+        i += CELLS_PER_WORD;
+    }
+    while i < count {
+        dst.add(i).write_volatile(value);
+        i += 1;
+    }
+}
+
 pub struct Writer {
     column_position: usize,
+    // how many of the rows above the last line a backspace can go back to
+    lines_above: usize,
     color_code: ColorCode,
     buffer: &'static mut Buffer,
+    // off-screen copy of the screen, all changes go here and `flush` copies it to `buffer`
+    shadow: [Line; BUFFER_HEIGHT],
+    // whether every write is flushed right away
+    auto_flush: bool,
+    // whether lines are broken between words, see `set_word_wrap`
+    word_wrap: bool,
+    // lines that were scrolled off the top of the screen, oldest first
+    history: VecDeque<Line>,
+    // how many lines the visible window is scrolled back into `history`
+    scroll_offset: usize,
+    // the live screen content, saved while the window is scrolled back
+    live_screen: [Line; BUFFER_HEIGHT],
+    escape: EscapeSequence,
+    // whether the last row is kept for `set_status` instead of the output
+    status_line: bool,
 }
 
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
+        self.put_byte(byte);
+        self.flush_if_auto();
+    }
+
+    /// Write `s`, characters outside of ASCII are shown with their CP437 glyph.
+    ///
+    /// Characters the VGA font has no glyph for are shown as `■`.
+    pub fn write_string(&mut self, s: &str) {
+        for (i, c) in s.char_indices() {
+            let byte = screen_byte(c);
+            if self.word_wrap && self.wrap_before(byte, &s[i..]) {
+                continue;
+            }
+            self.put_byte(byte);
+        }
+        // one flush for the whole string instead of one per byte
+        self.flush_if_auto();
+    }
+
+    /// Turn breaking lines between words instead of inside them on or off.
+    ///
+    /// Only words that fit on an empty line are moved, longer ones still wrap at
+    /// the end of the line. Off by default.
+    pub fn set_word_wrap(&mut self, enabled: bool) {
+        self.word_wrap = enabled;
+    }
+
+    // start a new line if the word starting at `rest` would not fit on this one,
+    // returns whether `byte` is a space that was replaced by the line break
+    fn wrap_before(&mut self, byte: u8, rest: &str) -> bool {
+        if !matches!(self.escape.state, EscapeState::Normal) {
+            return false;
+        }
+        if byte == b' ' && self.column_position >= BUFFER_WIDTH {
+            self.put_byte(b'\n');
+            return true;
+        }
+        // the previous cell tells whether this continues a word of an earlier write
+        let continues_word = self.column_position > 0
+            && self.column_position <= BUFFER_WIDTH
+            && is_word_byte(self.shadow[self.last_row()][self.column_position - 1].ascii_character);
+        if is_word_byte(byte) && !continues_word {
+            let word_len = rest
+                .chars()
+                .take_while(|&c| is_word_byte(screen_byte(c)))
+                .count();
+            if self.column_position + word_len > BUFFER_WIDTH && word_len <= BUFFER_WIDTH {
+                self.put_byte(b'\n');
+            }
+        }
+        false
+    }
+
+    // write the byte to the shadow buffer
+    fn put_byte(&mut self, byte: u8) {
+        if self.consume_escape(byte) {
+            return;
+        }
+        // new output always goes to the live screen
+        if self.scroll_offset != 0 {
+            self.scroll_offset = 0;
+            self.repaint();
+        }
         match byte {
             b'\n' => self.new_line(),
+            b'\t' => self.tab(),
+            0x08 => self.erase_previous(),
             byte => {
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
                 }
 
-                // always write at the last line of the screen
-                let row = BUFFER_HEIGHT - 1;
+                // always write at the last line of the output
+                let row = self.last_row();
                 let col = self.column_position;
 
                 let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
+                self.shadow[row][col] = ScreenChar {
                     ascii_character: byte,
                     color_code,
-                });
+                };
                 self.column_position += 1;
             }
         }
     }
 
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // for those not in ASCII range, print `■`
-                _ => self.write_byte(0xfe),
+    // blank up to the next tab stop, or start a new line if it is beyond the end of the line
+    fn tab(&mut self) {
+        let next_stop = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+        if next_stop > BUFFER_WIDTH {
+            self.new_line();
+            return;
+        }
+        let blank = self.blank();
+        let row = self.last_row();
+        for col in self.column_position..next_stop {
+            self.shadow[row][col] = blank;
+        }
+        self.column_position = next_stop;
+    }
+
+    /// Write `s` at the given position in the given color, e.g. for a status bar.
+    ///
+    /// The normal write position stays where it is. Text that does not fit is cut
+    /// off at the end of the row, a position outside of the screen writes nothing.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str, color: ColorCode) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        for (col, c) in (col..BUFFER_WIDTH).zip(s.chars()) {
+            self.shadow[row][col] = ScreenChar {
+                ascii_character: cp437_byte(c),
+                color_code: color,
+            };
+        }
+        self.flush_if_auto();
+    }
+
+    /// Keep the last row of the screen for a status line, or give it back to the output.
+    ///
+    /// While it is reserved, output scrolls in the rows above it and the status line
+    /// only changes with `set_status`. The lines on the screen move up or down by one
+    /// row so the current line stays the last line of the output.
+    pub fn reserve_status_line(&mut self, enabled: bool) {
+        if enabled == self.status_line {
+            return;
+        }
+        if enabled {
+            self.save_to_history(0);
+            self.shadow.copy_within(1.., 0);
+        } else {
+            self.shadow.copy_within(..BUFFER_HEIGHT - 1, 1);
+            self.clear_row(0);
+        }
+        self.clear_row(BUFFER_HEIGHT - 1);
+        self.status_line = enabled;
+        self.lines_above = self.lines_above.min(self.last_row());
+        self.flush_if_auto();
+    }
+
+    /// Replace the status line with `s` on a background of `color`.
+    ///
+    /// Does nothing unless the status line is reserved with `reserve_status_line`.
+    pub fn set_status(&mut self, s: &str, color: ColorCode) {
+        if !self.status_line {
+            return;
+        }
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: color,
+        };
+        self.shadow[BUFFER_HEIGHT - 1] = [blank; BUFFER_WIDTH];
+        self.write_at(BUFFER_HEIGHT - 1, 0, s, color);
+        // in case the window is scrolled back, the saved live screen has the status as well
+        self.live_screen[BUFFER_HEIGHT - 1] = self.shadow[BUFFER_HEIGHT - 1];
+    }
+
+    /// Draw a bar like `[█████░░░░░]` at the start of `row`, with `width` cells between the brackets.
+    ///
+    /// `percent` is clamped to 100 and `width` to what fits on a row. The write position
+    /// and the cursor stay where they are, so the bar can be redrawn in place.
+    pub fn draw_progress_bar(&mut self, row: usize, percent: u8, width: usize, fg: Color) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        let width = width.min(BUFFER_WIDTH - 2);
+        let filled = width * usize::from(percent.min(100)) / 100;
+        let color_code = self.color_code.with_foreground(fg as u8);
+        let cell = |c| ScreenChar {
+            ascii_character: cp437_byte(c),
+            color_code,
+        };
+        let line = &mut self.shadow[row];
+        line[0] = cell('[');
+        for (i, screen_char) in line[1..=width].iter_mut().enumerate() {
+            *screen_char = cell(if i < filled { '█' } else { '░' });
+        }
+        line[width + 1] = cell(']');
+        self.flush_if_auto();
+    }
+
+    // the row the output is written to, the status line is below it if reserved
+    fn last_row(&self) -> usize {
+        if self.status_line {
+            BUFFER_HEIGHT - 2
+        } else {
+            BUFFER_HEIGHT - 1
+        }
+    }
+
+    /// Read the character cell at `row` and `col` back from the screen.
+    ///
+    /// Returns a blank in the current color for coordinates outside the screen.
+    pub fn read_char_at(&self, row: usize, col: usize) -> ScreenChar {
+        if row >= BUFFER_HEIGHT || col >= BUFFER_WIDTH {
+            return self.blank();
+        }
+        self.buffer.chars[row][col].read()
+    }
+
+    /// Copy the shadow buffer to the screen and move the hardware cursor.
+    ///
+    /// Only needed after `set_auto_flush(false)`, otherwise every write is flushed.
+    pub fn flush(&mut self) {
+        // the rows of the shadow are contiguous as well, so the screen is copied in one go
+        unsafe {
+            let shadow = core::slice::from_raw_parts(
+                self.shadow.as_ptr() as *const ScreenChar,
+                BUFFER_HEIGHT * BUFFER_WIDTH,
+            );
+            copy_cells(self.buffer.cells(), shadow);
+        }
+        self.update_cursor();
+    }
+
+    /// Turn flushing after every write on or off.
+    ///
+    /// Turning it off lets bulk output be drawn at once with a single `flush`,
+    /// turning it back on flushes immediately.
+    pub fn set_auto_flush(&mut self, enabled: bool) {
+        self.auto_flush = enabled;
+        self.flush_if_auto();
+    }
+
+    fn flush_if_auto(&mut self) {
+        if self.auto_flush {
+            self.flush();
+        }
+    }
+
+    // feed the byte to the escape sequence parser,
+    // returns `false` if the byte is not part of an escape sequence and should be printed
+    fn consume_escape(&mut self, byte: u8) -> bool {
+        let escape = &mut self.escape;
+        match escape.state {
+            EscapeState::Normal => {
+                if byte != ESCAPE {
+                    return false;
+                }
+                escape.state = EscapeState::Escape;
+            }
+            EscapeState::Escape => {
+                if byte == b'[' {
+                    escape.state = EscapeState::Csi;
+                    escape.params = [0; MAX_ESCAPE_PARAMS];
+                    escape.index = 0;
+                } else {
+                    // only CSI sequences are supported, drop the others
+                    escape.state = EscapeState::Normal;
+                }
+            }
+            EscapeState::Csi => match byte {
+                b'0'..=b'9' => {
+                    if let Some(param) = escape.params.get_mut(escape.index) {
+                        *param = param
+                            .saturating_mul(10)
+                            .saturating_add(u16::from(byte - b'0'));
+                    }
+                }
+                b';' => escape.index += 1,
+                // the final byte ends the sequence, everything but SGR is swallowed
+                0x40..=0x7e => {
+                    escape.state = EscapeState::Normal;
+                    if byte == b'm' {
+                        self.apply_sgr();
+                    }
+                }
+                _ => {}
+            },
+        }
+        true
+    }
+
+    // apply the parameters of a `ESC [ ... m` (select graphic rendition) sequence
+    fn apply_sgr(&mut self) {
+        let count = (self.escape.index + 1).min(MAX_ESCAPE_PARAMS);
+        for i in 0..count {
+            match self.escape.params[i] {
+                0 => {
+                    self.color_code = ColorCode::default();
+                    self.escape.bold = false;
+                }
+                1 => {
+                    self.escape.bold = true;
+                    self.color_code = self
+                        .color_code
+                        .with_foreground(self.color_code.0 | BRIGHT_BIT);
+                }
+                code @ 30..=37 => {
+                    let mut foreground = ANSI_COLORS[usize::from(code - 30)] as u8;
+                    if self.escape.bold {
+                        foreground |= BRIGHT_BIT;
+                    }
+                    self.color_code = self.color_code.with_foreground(foreground);
+                }
+                code @ 40..=47 => {
+                    let background = ANSI_COLORS[usize::from(code - 40)] as u8;
+                    self.color_code = self.color_code.with_background(background);
+                }
+                _ => {}
             }
         }
     }
@@ -98,25 +570,146 @@ impl Writer {
     // move every character one line up (the top line gets deleted),
     // and start at the beginning of the last line again
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+        let last_row = self.last_row();
+        self.save_to_history(0);
+        self.shadow.copy_within(1..=last_row, 0);
+        self.clear_row(last_row);
+        self.column_position = 0;
+        self.lines_above = (self.lines_above + 1).min(last_row);
+    }
+
+    /// Erase the character before the current position.
+    ///
+    /// At the start of a line, the screen is scrolled back down by one row so that the
+    /// last character of the previous line gets erased. Nothing happens at the top-left corner.
+    pub fn backspace(&mut self) {
+        self.erase_previous();
+        self.flush_if_auto();
+    }
+
+    fn erase_previous(&mut self) {
+        let last_row = self.last_row();
+        if self.column_position == 0 {
+            if self.lines_above == 0 {
+                return;
             }
+            // the reverse of `new_line`, the top line becomes blank
+            self.shadow.copy_within(..last_row, 1);
+            self.clear_row(0);
+            self.lines_above -= 1;
+            self.column_position = BUFFER_WIDTH;
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+
+        self.column_position -= 1;
+        self.shadow[last_row][self.column_position] = self.blank();
     }
 
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
+    // keep a copy of the given row before it is scrolled off
+    fn save_to_history(&mut self, row: usize) {
+        // the history lives on the heap, so nothing is kept before `init_heap`
+        if !allocator::heap_initialized() {
+            return;
+        }
+        if self.history.len() == SCROLLBACK_LINES {
+            self.history.pop_front();
+        } else if self.history.try_reserve(1).is_err() {
+            // do not panic on a full heap, the panic handler would try to print
+            return;
+        }
+        let line = self.shadow[row];
+        self.history.push_back(line);
+    }
+
+    /// Scroll the visible window back by `lines` lines of history.
+    ///
+    /// The history is only recorded after the heap is initialized, so calling
+    /// it before `allocator::init_heap` is a no-op.
+    pub fn scroll_up(&mut self, lines: usize) {
+        if self.scroll_offset == 0 {
+            self.live_screen = self.shadow;
+        }
+        self.scroll_offset = (self.scroll_offset + lines).min(self.history.len());
+        self.repaint();
+        self.flush_if_auto();
+    }
+
+    /// Scroll the visible window forward by `lines` lines, towards the live output
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.scroll_offset == 0 {
+            return;
+        }
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+        self.repaint();
+        self.flush_if_auto();
+    }
+
+    /// Return to the live output
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_down(self.scroll_offset);
+    }
+
+    // draw the window `scroll_offset` lines above the live screen,
+    // which is the end of `history` followed by `live_screen`
+    fn repaint(&mut self) {
+        let first = self.history.len() - self.scroll_offset;
+        // the status line does not scroll
+        for row in 0..=self.last_row() {
+            let index = first + row;
+            self.shadow[row] = match self.history.get(index) {
+                Some(line) => *line,
+                None => self.live_screen[index - self.history.len()],
+            };
+        }
+    }
+
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
         }
     }
+
+    /// Use the given colors for all subsequent writes
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+
+    /// Blank the whole screen with the current color and move to the start of the line
+    ///
+    /// A reserved status line is kept.
+    pub fn clear_screen(&mut self) {
+        let blank = self.blank();
+        self.column_position = 0;
+        self.lines_above = 0;
+        if self.status_line {
+            for row in 0..=self.last_row() {
+                self.shadow[row] = [blank; BUFFER_WIDTH];
+            }
+            self.flush_if_auto();
+            return;
+        }
+        self.shadow = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        if self.auto_flush {
+            // the screen is all blanks, no need to copy the shadow
+            unsafe { fill_cells(self.buffer.cells(), BUFFER_HEIGHT * BUFFER_WIDTH, blank) };
+            self.update_cursor();
+        }
+    }
+
+    // move the hardware cursor to the current write position
+    fn update_cursor(&self) {
+        // the cursor stays at the last column after the line is full, until the next byte wraps it
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let position = (self.last_row() * BUFFER_WIDTH + col) as u16;
+        interrupts::without_interrupts(|| {
+            write_crtc(CRTC_CURSOR_LOCATION_LOW, (position & 0xff) as u8);
+            write_crtc(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+        });
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        self.shadow[row] = [self.blank(); BUFFER_WIDTH];
+    }
 }
 
 impl fmt::Write for Writer {
@@ -127,10 +720,163 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+    pub static ref WRITER: Mutex<Writer> = {
+        // a space in the default colors, the initial content of the screen
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: ColorCode::default(),
+        };
+        Mutex::new(Writer {
+            column_position: 0,
+            lines_above: 0,
+            color_code: ColorCode::default(),
+            // the bootloader identity maps it, until `remap` moves it
+            buffer: unsafe { &mut *(VGA_BUFFER_PHYS_ADDRESS as *mut Buffer) },
+            shadow: [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            auto_flush: true,
+            word_wrap: false,
+            history: VecDeque::new(),
+            scroll_offset: 0,
+            live_screen: [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            escape: EscapeSequence::new(),
+            status_line: false,
+        })
+    };
+}
+
+// physical address of the text buffer
+const VGA_BUFFER_PHYS_ADDRESS: u64 = 0xb8000;
+// where `remap` maps it, the 4000 bytes of the text buffer fit into this one page
+const VGA_BUFFER_VIRT_ADDRESS: u64 = 0x_5555_5555_0000;
+
+/// Map the text buffer to its own kernel page and switch the writer to it.
+///
+/// Until then the writer uses the identity mapping of the bootloader, which other
+/// page table setups might not have. The page is write-through and not cached.
+pub fn remap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(VGA_BUFFER_VIRT_ADDRESS));
+    let frame = PhysFrame::containing_address(PhysAddr::new(VGA_BUFFER_PHYS_ADDRESS));
+    let mut flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_CACHE;
+    if memory::no_execute_enabled() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    // the VGA buffer is not used for anything else, only the writer below accesses it
+    unsafe { memory::map_page(page, frame, flags, mapper, frame_allocator) }?;
+    interrupts::without_interrupts(|| {
+        // both addresses reach the same memory, so the screen content stays
+        WRITER.lock().buffer = unsafe { &mut *(VGA_BUFFER_VIRT_ADDRESS as *mut Buffer) };
+    });
+    Ok(())
+}
+
+// the glyphs of the CP437 bytes 0x80 to 0xff, the lower half is ASCII
+const CP437_UPPER_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// the byte the VGA font shows `c` with, `■` if it has no glyph for it
+fn cp437_byte(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        _ => CP437_UPPER_HALF
+            .iter()
+            .position(|&glyph| glyph == c)
+            .map_or(0xfe, |index| 0x80 + index as u8),
+    }
+}
+
+// the glyph of a CP437 byte, the control characters below 0x20 are shown as `.`
+fn cp437_char(byte: u8) -> char {
+    match byte {
+        0x20..=0x7e => char::from(byte),
+        0x80..=0xff => CP437_UPPER_HALF[usize::from(byte - 0x80)],
+        _ => '.',
+    }
+}
+
+/// The characters on row `row` of the screen, for `assert_vga_line!`
+#[doc(hidden)]
+pub fn _screen_row(row: usize) -> heapless::String<{ BUFFER_WIDTH * 4 }> {
+    // the timer interrupt prints as well, it must not find the writer locked
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        (0..BUFFER_WIDTH)
+            .map(|col| cp437_char(writer.read_char_at(row, col).ascii_character))
+            .collect()
+    })
+}
+
+/// Assert that row `row` of the screen shows `expected`, trailing spaces are ignored.
+///
+/// Reads the screen back, so the caller must not hold `WRITER`.
+#[macro_export]
+macro_rules! assert_vga_line {
+    ($row:expr, $expected:expr) => {
+        assert_eq!($crate::vga_buffer::_screen_row($row).trim_end(), $expected)
+    };
+}
+
+// like `cp437_byte`, but keeps the control characters the writer handles
+fn screen_byte(c: char) -> u8 {
+    match c {
+        // newline, tab, backspace or the start of an escape sequence
+        '\n' | '\t' | '\x08' | '\x1b' => c as u8,
+        _ => cp437_byte(c),
+    }
+}
+
+// bytes that are part of a word for word wrapping, CP437 glyphs above 0x7f count as letters
+fn is_word_byte(byte: u8) -> bool {
+    matches!(byte, 0x21..=0x7e) || byte >= 0x80
+}
+
+fn read_crtc(register: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        address.write(register);
+        data.read()
+    }
+}
+
+fn write_crtc(register: u8, value: u8) {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        address.write(register);
+        data.write(value);
+    }
+}
+
+/// Show the hardware cursor as an underline (scanlines 14 to 15 of the character cell)
+pub fn enable_cursor() {
+    interrupts::without_interrupts(|| {
+        // keep the reserved upper bits of both registers untouched
+        let start = read_crtc(CRTC_CURSOR_START);
+        write_crtc(CRTC_CURSOR_START, (start & 0xc0) | 14);
+        let end = read_crtc(CRTC_CURSOR_END);
+        write_crtc(CRTC_CURSOR_END, (end & 0xe0) | 15);
+    });
+}
+
+/// Hide the hardware cursor
+pub fn disable_cursor() {
+    interrupts::without_interrupts(|| {
+        // bit 5 of the cursor start register disables the cursor
+        write_crtc(CRTC_CURSOR_START, 0x20);
     });
 }
 
@@ -148,16 +894,223 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)))
 }
 
+// like `print!`, but never waits for the lock of the writer, e.g. for exception handlers
+// that may have interrupted code holding it. The output goes to serial instead then.
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::try_print!("\n"));
+    ($($arg:tt)*) => ($crate::try_print!("{}\n", format_args!($($arg)*)))
+}
+
+// print in the given colors, the previous color is restored afterwards
+// e.g. `with_color!(Color::Red, Color::Black, "error: {}", msg)`
+#[macro_export]
+macro_rules! with_color {
+    ($foreground:expr, $background:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_with_color($foreground, $background, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! clear_screen {
+    () => {
+        $crate::vga_buffer::_clear_screen()
+    };
+}
+
+static LOG_LEVEL: SinkLevel = SinkLevel::new();
+
+/// Only show `log` records of `level` or more severe on the screen, `Info` by default
+pub fn set_min_level(level: Level) {
+    LOG_LEVEL.set(level);
+}
+
+pub fn min_level() -> Level {
+    LOG_LEVEL.get()
+}
+
+// whether `print!` output also goes to serial, off by default so the timer dots stay off the log
+static TEE_SERIAL: AtomicBool = AtomicBool::new(false);
+
+/// Turn mirroring everything `print!` writes to the screen to serial on or off
+pub fn set_tee_serial(enabled: bool) {
+    TEE_SERIAL.store(enabled, Ordering::Relaxed);
+}
+
+// called with the writer locked, so the lock order is always WRITER before SERIAL1
+fn tee_serial(args: fmt::Arguments) {
+    if TEE_SERIAL.load(Ordering::Relaxed) {
+        serial::_print(args);
+    }
+}
+
 // hide it from the generated documentation, because it is a private implementation detail
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    klog::record(args);
     // ensure no interrupts can occur as long as the Mutex is locked
     // to avoid deadlocks (because the interrupt handler may call the function and try to acquire the lock)
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let mut writer = WRITER.lock();
+        writer.write_fmt(args).unwrap();
+        tee_serial(args);
+    });
+}
+
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) {
+    klog::try_record(args);
+    interrupts::without_interrupts(|| match WRITER.try_lock() {
+        Some(mut writer) => {
+            let _ = writer.write_fmt(args);
+            if TEE_SERIAL.load(Ordering::Relaxed) {
+                serial::try_print(args);
+            }
+        }
+        // serial may be locked as well, then the output is lost
+        None => {
+            serial::try_print(args);
+        }
+    });
+}
+
+/// Print `s` without recording it in the kernel log, for `klog::dump_klog`
+pub(crate) fn print_uncaptured(s: &str) {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_string(s);
+    });
+}
+
+// the color a running `_print_with_color` puts back, `NO_INTERRUPTED_COLOR` if none runs
+static INTERRUPTED_COLOR: AtomicU16 = AtomicU16::new(NO_INTERRUPTED_COLOR);
+const NO_INTERRUPTED_COLOR: u16 = u16::MAX;
+
+#[doc(hidden)]
+pub fn _print_with_color(foreground: Color, background: Color, args: fmt::Arguments) {
+    klog::record(args);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let previous = writer.color_code;
+        writer.set_color(foreground, background);
+        // with panic=abort nothing is dropped on a panic, so a guard could not restore
+        // the color, `restore_color_after_panic` does it from the panic handler
+        INTERRUPTED_COLOR.store(u16::from(previous.0), Ordering::SeqCst);
+        writer.write_fmt(args).unwrap();
+        writer.color_code = previous;
+        INTERRUPTED_COLOR.store(NO_INTERRUPTED_COLOR, Ordering::SeqCst);
+        tee_serial(args);
+    });
+}
+
+/// Clean up after a `with_color!` print that panicked partway, called by the panic handlers.
+///
+/// The print never continues, so its writer lock is released and the color it
+/// changed is put back. Does nothing if no colored print was interrupted.
+pub fn restore_color_after_panic() {
+    let color = INTERRUPTED_COLOR.swap(NO_INTERRUPTED_COLOR, Ordering::SeqCst);
+    if color == NO_INTERRUPTED_COLOR {
+        return;
+    }
+    // the panicked print holds the lock and never drops it
+    unsafe { WRITER.force_unlock() };
+    interrupts::without_interrupts(|| WRITER.lock().color_code = ColorCode(color as u8));
+}
+
+/// Writes formatted text at a fixed position, continuing on the next row at the end
+/// of a row. Unlike the normal output it never scrolls, so it does not allocate.
+struct PositionedWriter<'a> {
+    writer: &'a mut Writer,
+    row: usize,
+    col: usize,
+    color: ColorCode,
+}
+
+impl fmt::Write for PositionedWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, c) in s.char_indices() {
+            if c == '\n' || self.col >= BUFFER_WIDTH {
+                self.row += 1;
+                self.col = 0;
+            }
+            if c != '\n' {
+                self.writer
+                    .write_at(self.row, self.col, &s[i..i + c.len_utf8()], self.color);
+                self.col += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Paint a blue panic screen with `info` on it.
+///
+/// Does not allocate and does not wait for the writer, returns false if the writer
+/// is locked, e.g. because the panic happened while printing.
+pub fn show_panic_screen(info: &PanicInfo) -> bool {
+    const TITLE: &str = "KERNEL PANIC";
+
+    let mut writer = match WRITER.try_lock() {
+        Some(writer) => writer,
+        None => return false,
+    };
+    writer.scroll_offset = 0;
+    // paint into the shadow buffer only, the screen is copied once at the end
+    // instead of after every character of the message
+    let auto_flush = writer.auto_flush;
+    writer.auto_flush = false;
+    writer.set_color(Color::White, Color::Blue);
+    writer.clear_screen();
+    let color = writer.color_code;
+    writer.write_at(0, (BUFFER_WIDTH - TITLE.len()) / 2, TITLE, color);
+    let mut message = PositionedWriter {
+        writer: &mut writer,
+        row: 2,
+        col: 0,
+        color,
+    };
+    // the message is cut off at the bottom of the screen
+    let _ = write!(message, "{}", info);
+    writer.auto_flush = auto_flush;
+    writer.flush();
+    true
+}
+
+#[doc(hidden)]
+pub fn _clear_screen() {
+    // same as `_print`, a timer interrupt printing in the middle would leave garbage on the screen
+    interrupts::without_interrupts(|| {
+        WRITER.lock().clear_screen();
+    });
+}
+
+/// Draw a progress bar at `row` of the screen, see `Writer::draw_progress_bar`
+pub fn draw_progress_bar(row: usize, percent: u8, width: usize, fg: Color) {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().draw_progress_bar(row, percent, width, fg);
     });
 }
 
+#[test_case]
+fn test_color_code_packing() {
+    assert_eq!(ColorCode::new(Color::White, Color::Blue).0, 0x1f);
+    assert_eq!(ColorCode::new(Color::Black, Color::LightGray).0, 0x70);
+    assert_eq!(ColorCode::on_black(Color::LightGreen).0, 0x0a);
+    assert_eq!(ColorCode::DEFAULT.0, 0x0e);
+}
+
+#[test_case]
+fn test_color_from_u8() {
+    assert_eq!(Color::from_u8(0), Some(Color::Black));
+    assert_eq!(Color::from_u8(12), Some(Color::LightRed));
+    assert_eq!(Color::from_u8(15), Some(Color::White));
+    assert_eq!(Color::from_u8(16), None);
+}
+
 #[test_case]
 fn test_println_simple() {
     // just to verify println works without panic
@@ -171,6 +1124,21 @@ fn test_println_many() {
     }
 }
 
+/// Whether any row of the screen contains `s`, only works for ASCII
+#[cfg(test)]
+pub(crate) fn screen_contains(s: &str) -> bool {
+    // the timer interrupt prints as well, it must not find the writer locked
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        (0..BUFFER_HEIGHT).any(|row| {
+            let line: heapless::Vec<u8, BUFFER_WIDTH> = (0..BUFFER_WIDTH)
+                .map(|col| writer.read_char_at(row, col).ascii_character)
+                .collect();
+            line.windows(s.len()).any(|window| window == s.as_bytes())
+        })
+    })
+}
+
 #[test_case]
 fn test_println_output() {
     let s = "Some test string that fits on a single line";
@@ -184,3 +1152,420 @@ fn test_println_output() {
         }
     });
 }
+
+#[test_case]
+fn test_box_drawing_characters() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\n┌─┐é©").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[0].read().ascii_character, 0xda);
+        assert_eq!(row[1].read().ascii_character, 0xc4);
+        assert_eq!(row[2].read().ascii_character, 0xbf);
+        assert_eq!(row[3].read().ascii_character, 0x82);
+        // CP437 has no `©`, and each character takes one cell however long its UTF-8 is
+        assert_eq!(row[4].read().ascii_character, 0xfe);
+        assert_eq!(writer.column_position, 5);
+    });
+}
+
+#[test_case]
+fn test_try_println_does_not_wait_for_writer() {
+    interrupts::without_interrupts(|| {
+        let _writer = WRITER.lock();
+        // with the writer locked the output falls back to serial, lock that too so
+        // it is dropped instead of ending up in the middle of the test runner's line
+        let _serial = serial::SERIAL1.lock();
+        // `println!` would spin here forever
+        crate::try_println!("try_println while the writer is locked");
+    });
+    crate::try_println!("\ntry_println with the writer unlocked");
+    let last = interrupts::without_interrupts(|| WRITER.lock().read_char_at(BUFFER_HEIGHT - 2, 0));
+    assert_eq!(last.ascii_character, b't');
+}
+
+#[test_case]
+fn test_fill_cells_fills_screen() {
+    let marker = ScreenChar {
+        ascii_character: b'#',
+        color_code: ColorCode::DEFAULT,
+    };
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        unsafe { fill_cells(writer.buffer.cells(), BUFFER_HEIGHT * BUFFER_WIDTH, marker) };
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                assert_eq!(writer.buffer.chars[row][col].read(), marker);
+            }
+        }
+        // put the screen content back
+        writer.flush();
+    });
+}
+
+// Compare `fill_cells` with a loop over the cells. Timing under QEMU is too noisy
+// to assert on, so the cycles are only printed. Both put the screen content back
+// with `flush`, which is part of the numbers.
+#[cfg(test)]
+crate::bench! {
+    fn bench_fill_cells(20) {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let marker = ScreenChar {
+                ascii_character: b'#',
+                color_code: ColorCode::DEFAULT,
+            };
+            unsafe { fill_cells(writer.buffer.cells(), BUFFER_HEIGHT * BUFFER_WIDTH, marker) };
+            writer.flush();
+        });
+    }
+}
+
+#[cfg(test)]
+crate::bench! {
+    fn bench_fill_cell_loop(20) {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let marker = ScreenChar {
+                ascii_character: b'#',
+                color_code: ColorCode::DEFAULT,
+            };
+            let cells = writer.buffer.cells();
+            for i in 0..BUFFER_HEIGHT * BUFFER_WIDTH {
+                unsafe { cells.add(i).write_volatile(marker) };
+            }
+            writer.flush();
+        });
+    }
+}
+
+#[test_case]
+fn test_clear_screen() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "text that should be cleared").expect("writeln failed");
+        writer.clear_screen();
+        assert_eq!(writer.column_position, 0);
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let screen_char = writer.buffer.chars[row][col].read();
+                assert_eq!(screen_char.ascii_character, b' ');
+                assert_eq!(screen_char.color_code, writer.color_code);
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_with_color_restores_color() {
+    let previous = interrupts::without_interrupts(|| WRITER.lock().color_code);
+    with_color!(Color::Red, Color::Black, "\n{}", "x");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][0].read();
+        assert_eq!(char::from(screen_char.ascii_character), 'x');
+        assert_eq!(
+            screen_char.color_code,
+            ColorCode::new(Color::Red, Color::Black)
+        );
+        assert_eq!(writer.color_code, previous);
+    });
+}
+
+#[test_case]
+fn test_backspace() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nabc\x08\x08X").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[0].read().ascii_character, b'a');
+        assert_eq!(row[1].read().ascii_character, b'X');
+        assert_eq!(row[2].read().ascii_character, b' ');
+        assert_eq!(writer.column_position, 2);
+    });
+}
+
+#[test_case]
+fn test_backspace_top_left_corner() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.backspace();
+        assert_eq!(writer.column_position, 0);
+    });
+}
+
+#[test_case]
+fn test_cursor_follows_write_position() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nab").expect("write failed");
+        let high = read_crtc(CRTC_CURSOR_LOCATION_HIGH) as usize;
+        let low = read_crtc(CRTC_CURSOR_LOCATION_LOW) as usize;
+        assert_eq!(high << 8 | low, (BUFFER_HEIGHT - 1) * BUFFER_WIDTH + 2);
+    });
+}
+
+#[test_case]
+fn test_scroll_up() {
+    fn assert_row_starts_with(writer: &Writer, row: usize, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = writer.buffer.chars[row][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        for i in 0..30 {
+            writeln!(writer, "line {:02}", i).expect("writeln failed");
+        }
+        // the last line is empty, so the top row shows line 06
+        assert_row_starts_with(&writer, 0, "line 06");
+        writer.scroll_up(5);
+        assert_row_starts_with(&writer, 0, "line 01");
+        assert_row_starts_with(&writer, 5, "line 06");
+        writer.scroll_to_bottom();
+        assert_row_starts_with(&writer, 0, "line 06");
+        assert_row_starts_with(&writer, BUFFER_HEIGHT - 2, "line 29");
+    });
+}
+
+#[test_case]
+fn test_writer_uses_remapped_buffer() {
+    // `test_kernel_main` remaps the buffer before running the tests
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        assert_eq!(
+            writer.buffer as *const Buffer as u64,
+            VGA_BUFFER_VIRT_ADDRESS
+        );
+        write!(writer, "\nremapped").expect("write failed");
+        // the identity mapping still reaches the same memory
+        let physical = unsafe { &*(VGA_BUFFER_PHYS_ADDRESS as *const Buffer) };
+        for (i, c) in "remapped".chars().enumerate() {
+            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+            assert_eq!(physical.chars[BUFFER_HEIGHT - 1][i].read(), screen_char);
+        }
+    });
+}
+
+#[test_case]
+fn test_println_lands_on_screen_and_serial() {
+    serial::start_capture();
+    // no timer dots between the lines
+    interrupts::without_interrupts(|| {
+        set_tee_serial(true);
+        crate::println!("\nassert macros ┌─┐ {}", 1);
+        set_tee_serial(false);
+        crate::assert_vga_line!(BUFFER_HEIGHT - 2, "assert macros ┌─┐ 1");
+    });
+    serial::stop_capture();
+    crate::assert_serial_contains!("assert macros ┌─┐ 1\n");
+}
+
+#[test_case]
+fn test_status_line_survives_scrolling() {
+    let status_color = ColorCode::new(Color::Black, Color::LightGray);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.reserve_status_line(true);
+        writer.set_status("status: ok", status_color);
+        for i in 0..30 {
+            writeln!(writer, "line {:02}", i).expect("writeln failed");
+        }
+        writer.clear_screen();
+        write!(writer, "after clear").expect("write failed");
+
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        for (i, c) in "status: ok ".chars().enumerate() {
+            let screen_char = row[i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+            assert_eq!(screen_char.color_code, status_color);
+        }
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][0].read();
+        assert_eq!(screen_char.ascii_character, b'a');
+
+        writer.reserve_status_line(false);
+        // the output takes the last row back, without the status
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][0].read();
+        assert_eq!(screen_char.ascii_character, b'a');
+        writeln!(writer).expect("writeln failed");
+    });
+}
+
+#[test_case]
+fn test_ansi_color_sequence() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\n\x1b[31mred\x1b[0m").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        for (i, c) in "red".bytes().enumerate() {
+            let screen_char = row[i].read();
+            assert_eq!(screen_char.ascii_character, c);
+            assert_eq!(
+                screen_char.color_code,
+                ColorCode::new(Color::Red, Color::Black)
+            );
+        }
+        assert_eq!(row[3].read().ascii_character, b' ');
+        assert_eq!(writer.column_position, 3);
+        assert_eq!(writer.color_code, ColorCode::default());
+    });
+}
+
+#[test_case]
+fn test_ansi_unknown_sequence_is_swallowed() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\n\x1b[2Ja\x1b[1;32mb\x1b[m").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[0].read().ascii_character, b'a');
+        assert_eq!(row[1].read().ascii_character, b'b');
+        assert_eq!(
+            row[1].read().color_code,
+            ColorCode::new(Color::LightGreen, Color::Black)
+        );
+        assert_eq!(writer.column_position, 2);
+    });
+}
+
+#[test_case]
+fn test_flush_without_auto_flush() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.set_auto_flush(false);
+        for i in 0..50 {
+            writeln!(writer, "batched line {:02}", i).expect("writeln failed");
+        }
+        // nothing reached the screen yet
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][0].read();
+        assert_eq!(screen_char.ascii_character, b' ');
+
+        writer.flush();
+        for (i, c) in "batched line 49".bytes().enumerate() {
+            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            assert_eq!(screen_char.ascii_character, c);
+        }
+        writer.set_auto_flush(true);
+    });
+}
+
+#[test_case]
+fn test_write_at_top_right() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nab").expect("write failed");
+        let color = ColorCode::new(Color::White, Color::Blue);
+        // one character too many, the last one is cut off
+        writer.write_at(0, 74, "STATUS!", color);
+        // off screen, ignored
+        writer.write_at(BUFFER_HEIGHT, 0, "x", color);
+        writer.write_at(0, BUFFER_WIDTH, "x", color);
+
+        for (i, c) in "STATUS".bytes().enumerate() {
+            let screen_char = writer.buffer.chars[0][74 + i].read();
+            assert_eq!(screen_char.ascii_character, c);
+            assert_eq!(screen_char.color_code, color);
+        }
+        assert_eq!(writer.column_position, 2);
+        write!(writer, "c").expect("write failed");
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][2].read();
+        assert_eq!(screen_char.ascii_character, b'c');
+    });
+}
+
+#[test_case]
+fn test_tab_stops() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\na\tb").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[0].read().ascii_character, b'a');
+        for col in 1..8 {
+            assert_eq!(row[col].read().ascii_character, b' ');
+        }
+        assert_eq!(row[8].read().ascii_character, b'b');
+        // a tab right at a stop still moves to the next one
+        write!(writer, "\n12345678\tc").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[16].read().ascii_character, b'c');
+    });
+}
+
+#[test_case]
+fn test_read_char_at() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.write_at(0, 0, "Hi", ColorCode::default());
+        assert_eq!(writer.read_char_at(0, 0).ascii_character, b'H');
+        assert_eq!(writer.read_char_at(0, 1).ascii_character, b'i');
+        assert_eq!(writer.read_char_at(0, 1).color_code, ColorCode::default());
+        assert_eq!(writer.read_char_at(BUFFER_HEIGHT, 0), writer.blank());
+        assert_eq!(writer.read_char_at(0, BUFFER_WIDTH).ascii_character, b' ');
+    });
+}
+
+#[test_case]
+fn test_word_wrap() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_word_wrap(true);
+        // "boundary" would start at column 79 and end at 87
+        let s = "\nThis sentence is just long enough that its last word would cross over the line boundary";
+        write!(writer, "{}", s).expect("write failed");
+        writer.set_word_wrap(false);
+
+        let last = BUFFER_HEIGHT - 1;
+        for (i, c) in "boundary".bytes().enumerate() {
+            assert_eq!(writer.read_char_at(last, i).ascii_character, c);
+        }
+        // the previous line ends with "line" and blanks
+        for (i, c) in "line".bytes().enumerate() {
+            assert_eq!(writer.read_char_at(last - 1, 74 + i).ascii_character, c);
+        }
+        for col in 78..BUFFER_WIDTH {
+            assert_eq!(writer.read_char_at(last - 1, col).ascii_character, b' ');
+        }
+    });
+}
+
+#[test_case]
+fn test_progress_bar() {
+    const FULL_BLOCK: u8 = 0xdb;
+    const LIGHT_SHADE: u8 = 0xb0;
+
+    draw_progress_bar(3, 20, 10, Color::Green);
+    // drawn again in place
+    draw_progress_bar(3, 50, 10, Color::Green);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let bar: [u8; 12] = core::array::from_fn(|col| writer.read_char_at(3, col).ascii_character);
+        assert_eq!(bar[0], b'[');
+        assert_eq!(bar[11], b']');
+        assert_eq!(bar.iter().filter(|&&c| c == FULL_BLOCK).count(), 5);
+        assert_eq!(bar.iter().filter(|&&c| c == LIGHT_SHADE).count(), 5);
+        assert_eq!(bar[1..6], [FULL_BLOCK; 5]);
+        let color = writer.read_char_at(3, 1).color_code;
+        assert_eq!(color, writer.color_code.with_foreground(Color::Green as u8));
+    });
+
+    // clamped to the screen and to 100 percent
+    draw_progress_bar(3, 200, 1000, Color::Green);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!(
+            writer.read_char_at(3, BUFFER_WIDTH - 2).ascii_character,
+            FULL_BLOCK
+        );
+        assert_eq!(
+            writer.read_char_at(3, BUFFER_WIDTH - 1).ascii_character,
+            b']'
+        );
+    });
+}
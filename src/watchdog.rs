@@ -0,0 +1,38 @@
+use crate::interrupts;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// ticks allowed between two kicks, 0 while the watchdog is disarmed
+static TIMEOUT: AtomicU64 = AtomicU64::new(0);
+static LAST_KICK: AtomicU64 = AtomicU64::new(0);
+
+/// Start the watchdog, it panics if `kick` is not called for `ticks` timer ticks.
+///
+/// Arming it again just changes the timeout and counts as a kick.
+pub fn arm(ticks: u64) {
+    assert!(ticks > 0, "watchdog timeout must not be zero");
+    kick();
+    TIMEOUT.store(ticks, Ordering::SeqCst);
+}
+
+pub fn disarm() {
+    TIMEOUT.store(0, Ordering::SeqCst);
+}
+
+/// Tell the watchdog the kernel is still alive
+pub fn kick() {
+    LAST_KICK.store(interrupts::ticks(), Ordering::SeqCst);
+}
+
+/// Called by the timer interrupt handler, panics once the timeout has passed without a kick
+pub(crate) fn check() {
+    let timeout = TIMEOUT.load(Ordering::SeqCst);
+    if timeout == 0 {
+        return;
+    }
+    let idle = interrupts::ticks().saturating_sub(LAST_KICK.load(Ordering::SeqCst));
+    if idle >= timeout {
+        // the panic handler may take longer than a tick, do not panic again
+        disarm();
+        panic!("watchdog: not kicked for {} ticks", idle);
+    }
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::acpi;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { acpi::init(phys_mem_offset) }.expect("ACPI initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn rsdp_is_found() {
+    let rsdp = acpi::find_rsdp().expect("no RSDP");
+    // the RSDP is below 1 MiB
+    assert!(rsdp.as_u64() < 0x100000);
+}
+
+#[test_case]
+fn madt_is_found() {
+    let madt = acpi::find_table(acpi::MADT).expect("no MADT");
+    let header = unsafe { acpi::table_header(madt) };
+    assert_eq!(header.signature, acpi::MADT);
+}
+
+#[test_case]
+fn unknown_table_is_not_found() {
+    assert_eq!(acpi::find_table(*b"NONE"), None);
+}
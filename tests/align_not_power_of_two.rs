@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use rust_os::{fail_qemu, serial_print, serial_println, success_qemu};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("align_not_power_of_two::align_up_asserts...\t");
+    // the check is a `debug_assert!`, tests are built in debug mode
+    rust_os::allocator::align_up(0x1000, 3);
+    serial_println!("[test did not panic]");
+    fail_qemu()
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    success_qemu()
+}
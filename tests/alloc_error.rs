@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use rust_os::allocator::HEAP_SIZE;
+use rust_os::memory::BootInfoFrameAllocator;
+use rust_os::{allocator, fail_qemu, memory, serial_print, serial_println, success_qemu};
+use x86_64::VirtAddr;
+
+// larger than the whole heap, so the allocation must fail
+const REQUESTED_SIZE: usize = HEAP_SIZE + 1;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("alloc_error::exhaust_heap...\t");
+
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let vec: Vec<u8> = Vec::with_capacity(REQUESTED_SIZE);
+    serial_println!("[allocation did not fail]");
+    drop(vec);
+    fail_qemu()
+}
+
+// since it exits on the first allocation error
+// it does not make sense to exhaust the heap more than once
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    allocator::report_alloc_error(layout);
+    if layout.size() == REQUESTED_SIZE {
+        serial_println!("[ok]");
+        success_qemu()
+    } else {
+        serial_println!("[failed]\nunexpected layout {:?}", layout);
+        fail_qemu()
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{apic, interrupts};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    apic::init(&mut mapper, &mut frame_allocator).expect("APIC initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn apic_timer_ticks() {
+    assert!(apic::is_enabled());
+    let start = interrupts::ticks();
+    // with the PIC masked, only the APIC timer can advance the counter
+    while interrupts::ticks() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+}
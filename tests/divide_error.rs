@@ -0,0 +1,54 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("divide_error::divide_error...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    // `/` checks for zero and panics, so divide with the instruction directly
+    unsafe {
+        asm!(
+            "xor edx, edx",
+            "mov eax, 1",
+            "xor ecx, ecx",
+            "div ecx",
+            out("eax") _,
+            out("ecx") _,
+            out("edx") _,
+        );
+    }
+
+    panic!("Execution continued after divide error");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(test_divide_error_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_divide_error_handler(_stack_frame: InterruptStackFrame) {
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
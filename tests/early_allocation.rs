@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::BootInfoFrameAllocator;
+use rust_os::{allocator, memory};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// allocated before the heap exists, checked by the tests afterwards
+static EARLY_BOX: Mutex<Option<Box<[u64; 4]>>> = Mutex::new(None);
+static EARLY_VEC: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+
+    *EARLY_BOX.lock() = Some(Box::new([1, 2, 3, 4]));
+    *EARLY_VEC.lock() = Some((0..16).collect());
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn early_box_survives_heap_init() {
+    let early = EARLY_BOX.lock().take().unwrap();
+    assert!(allocator::is_early_allocation(early.as_ptr().cast()));
+    assert_eq!(*early, [1, 2, 3, 4]);
+
+    let late = Box::new(5u64);
+    assert!(!allocator::is_early_allocation(
+        (&*late as *const u64).cast()
+    ));
+    // freeing an early allocation after the switch is allowed
+    drop(early);
+}
+
+#[test_case]
+fn early_vec_moves_to_heap_on_growth() {
+    let mut vec = EARLY_VEC.lock().take().unwrap();
+    assert!(allocator::is_early_allocation(vec.as_ptr()));
+    // too large for the early heap, so growing has to reallocate on the real heap
+    vec.reserve(allocator::EARLY_HEAP_SIZE);
+    assert!(!allocator::is_early_allocation(vec.as_ptr()));
+    assert!(vec.iter().take(16).copied().eq(0..16));
+}
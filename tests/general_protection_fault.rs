@@ -0,0 +1,54 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::interrupts::{DescriptorTable, SelectorErrorCode};
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+// GDT entry 0x246 is far beyond the end of our GDT
+const BAD_SELECTOR: u16 = 0x1230;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("general_protection_fault::general_protection_fault...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    unsafe { asm!("mov ds, {0:x}", in(reg) BAD_SELECTOR) };
+
+    panic!("Execution continued after general protection fault");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.general_protection_fault
+            .set_handler_fn(test_general_protection_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_general_protection_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let selector = SelectorErrorCode(error_code);
+    assert_eq!(selector.table(), DescriptorTable::Gdt);
+    assert_eq!(selector.index(), u64::from(BAD_SELECTOR >> 3));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
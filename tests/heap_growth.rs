@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc};
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use rust_os::allocator::{self, HEAP_SIZE};
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::structures::paging::OffsetPageTable;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn allocation_succeeds_after_growing() {
+    // larger than any block size, so every allocation takes new heap memory
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+
+    // `alloc` returns null instead of calling the alloc error handler
+    let mut filled = false;
+    for _ in 0..=HEAP_SIZE / layout.size() {
+        if unsafe { alloc(layout) }.is_null() {
+            filled = true;
+            break;
+        }
+    }
+    assert!(filled, "heap did not run out");
+
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    allocator::grow_heap(
+        16,
+        mapper.as_mut().unwrap(),
+        frame_allocator.as_mut().unwrap(),
+    )
+    .expect("growing the heap failed");
+    assert_eq!(allocator::heap_size(), HEAP_SIZE + 16 * 4096);
+
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe {
+        ptr.write_bytes(0xAB, layout.size());
+        dealloc(ptr, layout);
+    }
+}
+
+#[test_case]
+fn aligned_growth_uses_huge_pages() {
+    use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+    use x86_64::structures::paging::Translate;
+
+    const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let old_end = allocator::HEAP_START + allocator::heap_size();
+    // 4 MiB always contain a whole 2 MiB aligned block
+    allocator::grow_heap(
+        1024,
+        mapper.as_mut().unwrap(),
+        frame_allocator.as_mut().unwrap(),
+    )
+    .expect("growing the heap failed");
+
+    let huge_start = (old_end + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1);
+    match mapper
+        .as_ref()
+        .unwrap()
+        .translate(VirtAddr::new(huge_start as u64 + 0x1234))
+    {
+        TranslateResult::Mapped { frame, .. } => {
+            assert!(matches!(frame, MappedFrame::Size2MiB(_)), "{:?}", frame)
+        }
+        other => panic!("heap not mapped: {:?}", other),
+    }
+    // the heap memory in the huge page is usable
+    let ptr = (huge_start + 0x1234) as *mut u64;
+    unsafe {
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+}
@@ -0,0 +1,64 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::allocator::{self, HEAP_SIZE, HEAP_START};
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_guard_page::write_past_heap_end...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    // the last byte of the heap is fine, the next one is in the guard page
+    let last = (HEAP_START + HEAP_SIZE - 1) as *mut u8;
+    unsafe { last.write_volatile(42) };
+    let past_end = (HEAP_START + HEAP_SIZE) as *mut u8;
+    unsafe { past_end.write_volatile(42) };
+
+    panic!("Execution continued after writing past the heap end");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert_eq!(Cr2::read(), VirtAddr::new((HEAP_START + HEAP_SIZE) as u64));
+    // not present, not a protection violation
+    assert!(!error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
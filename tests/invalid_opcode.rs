@@ -0,0 +1,45 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_opcode::invalid_opcode...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    // `ud2` is guaranteed to raise #UD
+    unsafe { asm!("ud2") };
+
+    panic!("Execution continued after invalid opcode");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.invalid_opcode
+            .set_handler_fn(test_invalid_opcode_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_invalid_opcode_handler(_stack_frame: InterruptStackFrame) {
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
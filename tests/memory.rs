@@ -0,0 +1,371 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::bootinfo::MemoryMap;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::allocator::{self, HEAP_START};
+use rust_os::bench;
+use rust_os::memory::{self, BitmapFrameAllocator, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size2MiB,
+    Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+static MEMORY_MAP: Mutex<Option<&'static MemoryMap>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    *MEMORY_MAP.lock() = Some(&boot_info.memory_map);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn deallocated_frame_is_reused() {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    let reused: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    assert_eq!(reused.start_address(), frame.start_address());
+}
+
+#[test_case]
+fn reused_frame_is_zeroed() {
+    let mapper = MAPPER.lock();
+    let phys_offset = mapper.as_ref().unwrap().phys_offset();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    let ptr: *mut u64 = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+    for i in 0..512 {
+        unsafe { ptr.add(i).write_volatile(0xdead_beef) };
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    let reused: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    assert_eq!(reused, frame);
+    for i in 0..512 {
+        assert_eq!(unsafe { ptr.add(i).read_volatile() }, 0);
+    }
+}
+
+// allocate and free the same frame again and again, with and without zeroing it
+bench! {
+    fn bench_reuse_frame_zeroed(1000) {
+        reuse_frame(true);
+    }
+}
+
+bench! {
+    fn bench_reuse_frame_not_zeroed(1000) {
+        reuse_frame(false);
+    }
+}
+
+fn reuse_frame(zero_frames: bool) {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    frame_allocator.set_zero_frames(zero_frames);
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    frame_allocator.set_zero_frames(true);
+}
+
+#[test_case]
+fn stack_has_guard_page() {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let stack = memory::allocate_stack(4, mapper, frame_allocator).expect("allocate_stack failed");
+    assert_eq!(stack.end() - stack.start(), 4 * 4096);
+
+    // the whole stack is writable
+    let lowest: *mut u64 = stack.start().as_mut_ptr();
+    let highest: *mut u64 = (stack.end() - 8u64).as_mut_ptr();
+    unsafe {
+        lowest.write_volatile(1);
+        highest.write_volatile(2);
+        assert_eq!(lowest.read_volatile(), 1);
+        assert_eq!(highest.read_volatile(), 2);
+    }
+    // but the page below it is not mapped
+    assert!(mapper.translate_addr(stack.start() - 1u64).is_none());
+
+    // a second stack does not overlap the first one or its guard page
+    let next = memory::allocate_stack(1, mapper, frame_allocator).expect("allocate_stack failed");
+    assert!(next.start() > stack.end());
+}
+
+#[test_case]
+fn mapping_flags_round_trip() {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let page = Page::containing_address(VirtAddr::new(0x_7777_7777_0000));
+    let frame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    // only translated, never accessed, so it does not matter whether NXE is enabled
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    unsafe { memory::map_page(page, frame, flags, mapper, frame_allocator) }
+        .expect("map_page failed");
+
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: mapped_frame,
+            offset,
+            flags: mapped_flags,
+        } => {
+            assert_eq!(mapped_frame.start_address(), frame.start_address());
+            assert_eq!(offset, 0);
+            assert!(mapped_flags.contains(PageTableFlags::NO_EXECUTE));
+            assert!(!mapped_flags.contains(PageTableFlags::WRITABLE));
+        }
+        _ => panic!("page is not mapped"),
+    }
+}
+
+#[test_case]
+fn mmio_mapping_is_uncached() {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let page = Page::containing_address(VirtAddr::new(0x_7777_aaaa_0000));
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    unsafe { memory::map_mmio(page, frame, mapper, frame_allocator) }.expect("map_mmio failed");
+
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: mapped_frame,
+            flags,
+            ..
+        } => {
+            assert_eq!(mapped_frame.start_address(), frame.start_address());
+            assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+            assert!(flags.contains(PageTableFlags::WRITABLE));
+            assert!(!flags.contains(PageTableFlags::HUGE_PAGE));
+        }
+        _ => panic!("page is not mapped"),
+    }
+}
+
+#[test_case]
+fn map_range_round_trip() {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    const PAGES: usize = 10;
+    let start = Page::containing_address(VirtAddr::new(0x_7777_8888_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::map_range(start, PAGES, flags, mapper, frame_allocator).expect("map_range failed");
+
+    let words = PAGES * 4096 / 8;
+    let ptr: *mut u64 = start.start_address().as_mut_ptr();
+    for i in 0..words {
+        unsafe { ptr.add(i).write_volatile(i as u64 ^ 0x5555) };
+    }
+    for i in 0..words {
+        assert_eq!(unsafe { ptr.add(i).read_volatile() }, i as u64 ^ 0x5555);
+    }
+
+    memory::unmap_range(start, PAGES, mapper, frame_allocator).expect("unmap_range failed");
+    for page in Page::range(start, start + PAGES as u64) {
+        assert!(mapper.translate_addr(page.start_address()).is_none());
+    }
+}
+
+#[test_case]
+fn map_range_rolls_back_on_failure() {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    // the last page of the range is already mapped
+    let start = Page::containing_address(VirtAddr::new(0x_7777_9999_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::map_range(start + 3, 1, flags, mapper, frame_allocator).expect("map_range failed");
+
+    let result = memory::map_range(start, 4, flags, mapper, frame_allocator);
+    assert!(matches!(result, Err(MapToError::PageAlreadyMapped(_))));
+    for page in Page::range(start, start + 3) {
+        assert!(mapper.translate_addr(page.start_address()).is_none());
+    }
+    assert!(mapper.translate_addr((start + 3).start_address()).is_some());
+}
+
+#[test_case]
+fn vga_buffer_translates_to_0xb8000() {
+    let mapper = MAPPER.lock();
+    let mapper = mapper.as_ref().unwrap();
+
+    // the VGA buffer is identity mapped
+    let vga = memory::translate_addr(VirtAddr::new(0xb8000), mapper);
+    assert_eq!(vga, Some(PhysAddr::new(0xb8000)));
+    // and like all physical memory also mapped at the offset
+    let through_offset = memory::translate_addr(mapper.phys_offset() + 0xb8123u64, mapper);
+    assert_eq!(through_offset, Some(PhysAddr::new(0xb8123)));
+}
+
+#[test_case]
+fn memory_stats_sum_regions() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+
+    let mut memory_map = MemoryMap::new();
+    let regions = [
+        (0x0000, 0x1000, MemoryRegionType::FrameZero),
+        (0x1000, 0x9000, MemoryRegionType::Usable),
+        (0x9000, 0xA000, MemoryRegionType::Reserved),
+        (0x10_0000, 0x20_0000, MemoryRegionType::Usable),
+        (0x20_0000, 0x20_4000, MemoryRegionType::Kernel),
+    ];
+    for (start, end, region_type) in regions {
+        memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type,
+        });
+    }
+
+    let stats = memory::memory_stats(&memory_map);
+    assert_eq!(stats.usable_bytes, 0x8000 + 0x10_0000);
+    assert_eq!(stats.reserved_bytes, 0x1000);
+    assert_eq!(
+        stats.total_bytes,
+        0x1000 + 0x8000 + 0x1000 + 0x10_0000 + 0x4000
+    );
+    assert_eq!(stats.frame_count, 8 + 256);
+}
+
+#[test_case]
+fn page_table_walk_of_heap_start() {
+    let mapper = MAPPER.lock();
+    let mapper = mapper.as_ref().unwrap();
+    // the heap is mapped with 4 KiB pages, so the walk goes through all levels
+    assert_eq!(
+        memory::walk_page_table(VirtAddr::new(HEAP_START as u64), mapper),
+        4
+    );
+    // nothing maps this region, so the walk stops early
+    assert!(memory::walk_page_table(VirtAddr::new(0x_5555_0000_0000), mapper) < 4);
+}
+
+#[test_case]
+fn frames_allocated_counts_allocations() {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let allocated = frame_allocator.frames_allocated();
+    let remaining = frame_allocator.remaining();
+    for _ in 0..5 {
+        let _: PhysFrame = frame_allocator
+            .allocate_frame()
+            .expect("no frame available");
+    }
+    assert_eq!(frame_allocator.frames_allocated(), allocated + 5);
+    assert_eq!(frame_allocator.remaining(), remaining - 5);
+}
+
+#[test_case]
+fn usable_frames_match_memory_stats() {
+    let memory_map = MEMORY_MAP.lock().unwrap();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    let remaining = frame_allocator.remaining();
+    let count = frame_allocator.usable_frames().count();
+    assert_eq!(count, memory::memory_stats(memory_map).frame_count);
+    // iterating does not hand out frames
+    assert_eq!(frame_allocator.remaining(), remaining);
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    assert!(frame_allocator
+        .usable_frames()
+        .any(|usable| usable == frame));
+}
+
+#[test_case]
+fn bitmap_allocator_reuses_frames() {
+    let phys_offset = MAPPER.lock().as_ref().unwrap().phys_offset();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+
+    // frames of its own, one of them keeps the bitmap
+    let frames: [PhysFrame; 16] = core::array::from_fn(|_| {
+        frame_allocator
+            .allocate_frame()
+            .expect("no frame available")
+    });
+    let mut bitmap =
+        unsafe { BitmapFrameAllocator::from_frames(frames.iter().copied(), phys_offset) }
+            .expect("no room for the bitmap");
+    assert_eq!(bitmap.free_frames(), 15);
+
+    let first: PhysFrame = bitmap.allocate_frame().expect("no frame available");
+    let second: PhysFrame = bitmap.allocate_frame().expect("no frame available");
+    assert_ne!(first, second);
+    assert!(frames.contains(&first) && frames.contains(&second));
+    assert!(bitmap.is_allocated(first) && bitmap.is_allocated(second));
+
+    unsafe { bitmap.deallocate_frame(first) };
+    assert!(!bitmap.is_allocated(first));
+    assert_eq!(bitmap.free_frames(), 14);
+    let reused: PhysFrame = bitmap.allocate_frame().expect("no frame available");
+    assert_eq!(reused, first);
+    assert!(bitmap.is_allocated(reused));
+
+    let mut allocated = 0;
+    while let Some(frame) = FrameAllocator::<Size4KiB>::allocate_frame(&mut bitmap) {
+        assert!(frames.contains(&frame));
+        allocated += 1;
+    }
+    assert_eq!(allocated, 13);
+    assert_eq!(bitmap.free_frames(), 0);
+    // 16 frames are never a free 2 MiB frame
+    let huge: Option<PhysFrame<Size2MiB>> = bitmap.allocate_frame();
+    assert!(huge.is_none());
+}
@@ -0,0 +1,76 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use rust_os::allocator;
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+// `ret`
+const RET: u8 = 0xc3;
+
+// address of the code that is put on the heap
+static CODE_ADDRESS: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("no_execute::execute_heap...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+    memory::enable_no_execute();
+    assert!(memory::no_execute_enabled(), "the CPU does not support NX");
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let code = Box::new([RET; 16]);
+    let code_ptr = Box::into_raw(code) as *const u8;
+    CODE_ADDRESS.store(code_ptr as u64, Ordering::SeqCst);
+    let function: extern "C" fn() = unsafe { core::mem::transmute(code_ptr) };
+    function();
+
+    panic!("Execution continued after executing heap memory");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert_eq!(Cr2::read().as_u64(), CODE_ADDRESS.load(Ordering::SeqCst));
+    assert!(error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH));
+    assert!(error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,55 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::{serial_print, serial_try_print, serial_try_println, success_qemu};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+const FAULT_ADDRESS: u64 = 0xdeadbeef;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_fault::page_fault...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    // access an unmapped address
+    let ptr = FAULT_ADDRESS as *mut u8;
+    unsafe { ptr.write_volatile(42) };
+
+    panic!("Execution continued after page fault");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = Cr2::read();
+    serial_try_print!("Accessed Address: {:?}\t", address);
+    assert_eq!(address, VirtAddr::new(FAULT_ADDRESS));
+    assert!(error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,75 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const PAGE_ADDRESS: u64 = 0x_5555_6666_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("read_only_page::write_to_read_only_page...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+
+    let page = Page::containing_address(VirtAddr::new(PAGE_ADDRESS));
+    let frame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    // no `WRITABLE`
+    let flags = PageTableFlags::PRESENT;
+    // the frame was just allocated
+    unsafe { memory::map_page(page, frame, flags, &mut mapper, &mut frame_allocator) }
+        .expect("map_page failed");
+
+    // reading is fine
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe { ptr.read_volatile() };
+    unsafe { ptr.write_volatile(42) };
+
+    panic!("Execution continued after writing to a read-only page");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert_eq!(Cr2::read(), VirtAddr::new(PAGE_ADDRESS));
+    // the page is present, the write is what is not allowed
+    assert!(error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    assert!(error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
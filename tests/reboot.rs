@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rust_os::{fail_qemu, serial_print, serial_println, success_qemu};
+
+// the panic that stands in for the reset, anything else is a failure
+const RESET_REACHED: &str = "reached the PS/2 reset";
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("reboot::ps2_reset_is_reachable...\t");
+    // a fault on the way panics with a different message
+    rust_os::gdt::init();
+    rust_os::interrupts::init_idt();
+
+    // the reset command itself would end the test, so stop right before it
+    assert!(rust_os::prepare_reboot(), "the PS/2 controller stays busy");
+    panic!("{}", RESET_REACHED);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // a formatted message has no `as_str`, so compare it written out
+    let mut message: heapless::String<64> = heapless::String::new();
+    let _ = write!(message, "{}", info.message());
+    if message == RESET_REACHED {
+        serial_println!("[ok]");
+        success_qemu()
+    }
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    fail_qemu()
+}
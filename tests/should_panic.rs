@@ -3,14 +3,13 @@
 #![feature(custom_test_frameworks)]
 
 use core::panic::PanicInfo;
-use rust_os::{exit_qemu, QemuExitCode, serial_print, serial_println};
+use rust_os::{fail_qemu, serial_print, serial_println, success_qemu};
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     should_fail();
     serial_println!("[test did not panic]");
-    exit_qemu(QemuExitCode::Failed);
-    loop {}
+    fail_qemu()
 }
 
 // since it exits after running a single test
@@ -18,8 +17,7 @@ pub extern "C" fn _start() -> ! {
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     serial_println!("[ok]");
-    exit_qemu(QemuExitCode::Success);
-    loop {}
+    success_qemu()
 }
 
 fn should_fail() {
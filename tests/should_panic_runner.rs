@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os::should_panic_test;
+
+// incremented by `test_assert_fails` right before it panics
+static PANICKED_TESTS: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_main();
+
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+should_panic_test! {
+    fn test_assert_fails() {
+        PANICKED_TESTS.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(0, 1);
+    }
+}
+
+#[test_case]
+fn test_runs_after_panic() {
+    // only reached if the runner carried on after the panic of `test_assert_fails`
+    assert_eq!(PANICKED_TESTS.load(Ordering::SeqCst), 1);
+}
+
+should_panic_test! {
+    fn test_explicit_panic() {
+        panic!("expected panic");
+    }
+}
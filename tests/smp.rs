@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{acpi, apic, smp};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// what `start_aps` returned
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    unsafe { acpi::init(phys_mem_offset) }.expect("ACPI initialization failed");
+    apic::init(&mut mapper, &mut frame_allocator).expect("APIC initialization failed");
+    let started =
+        smp::start_aps(&mut mapper, &mut frame_allocator).expect("starting the APs failed");
+    STARTED.store(started, Ordering::SeqCst);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_lists_two_cpus() {
+    // QEMU runs the tests with `-smp 2`
+    assert_eq!(smp::cpu_count(), 2);
+}
+
+#[test_case]
+fn ap_reports_alive() {
+    assert_eq!(STARTED.load(Ordering::SeqCst), 1);
+    assert!(smp::aps_alive() >= 1);
+}
@@ -5,7 +5,7 @@
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-use rust_os::{exit_qemu, QemuExitCode, serial_print, serial_println};
+use rust_os::{serial_print, serial_try_println, success_qemu};
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
@@ -49,9 +49,8 @@ extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    serial_println!("[ok]");
-    exit_qemu(QemuExitCode::Success);
-    loop {}
+    serial_try_println!("[ok]");
+    success_qemu()
 }
 
 #[panic_handler]
@@ -0,0 +1,71 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::{fail_qemu, serial_print, serial_try_println, success_qemu};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("stack_overflow_page_fault::stack_overflow...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    // the overflow hits the guard page below the kernel stack
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    // for each recursion, the return address is pushed
+    stack_overflow();
+    // prevent tail recursion optimizations
+    volatile::Volatile::new(0).read();
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(test_page_fault_handler)
+                .set_stack_index(rust_os::gdt::PAGE_FAULT_IST_INDEX);
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(rust_os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+// without its own stack the page fault handler could not run and the CPU raises a double fault
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    serial_try_println!("[failed]\n");
+    serial_try_println!("Error: double fault instead of page fault\n");
+    fail_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static MEMORY_TESTS_RUN: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rust_os::set_test_filter("memory");
+    test_main();
+
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn memory_first() {
+    assert_eq!(MEMORY_TESTS_RUN.fetch_add(1, Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn unrelated() {
+    panic!("filtered test was run");
+}
+
+#[test_case]
+fn memory_second() {
+    // `memory_first` ran and `unrelated` was skipped in between
+    assert_eq!(MEMORY_TESTS_RUN.fetch_add(1, Ordering::SeqCst), 1);
+}
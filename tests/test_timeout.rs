@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use rust_os::QemuExitCode;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // the timer interrupt is what notices the stuck test
+    rust_os::init();
+    // the only test in here is supposed to time out
+    rust_os::set_timeout_exit_code(QemuExitCode::Success);
+    test_main();
+
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_spins_forever() {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,77 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{serial_print, serial_try_println, success_qemu};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const PAGE_ADDRESS: u64 = 0x_5555_5555_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("unmap_page::access_after_unmap...\t");
+
+    rust_os::gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+
+    let page = Page::containing_address(VirtAddr::new(PAGE_ADDRESS));
+    let frame = frame_allocator
+        .allocate_frame()
+        .expect("no frame available");
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) }
+        .expect("map_to failed")
+        .flush();
+
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe { ptr.write_volatile(42) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 42);
+
+    let unmapped_frame = memory::unmap_page(page, &mut mapper).expect("unmap_page failed");
+    assert_eq!(unmapped_frame, frame);
+    // unmapping twice reports the error instead of panicking
+    assert!(memory::unmap_page(page, &mut mapper).is_err());
+
+    unsafe { ptr.read_volatile() };
+
+    panic!("Execution continued after accessing an unmapped page");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    assert_eq!(Cr2::read(), VirtAddr::new(PAGE_ADDRESS));
+    serial_try_println!("[ok]");
+    success_qemu()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rust_os::{fail_qemu, serial_print, serial_println, success_qemu};
+
+// the start of the watchdog's panic message, anything else is a failure
+const WATCHDOG_FIRED: &str = "watchdog: not kicked";
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("watchdog::fires_without_kick...\t");
+
+    // the timer interrupt is what checks the watchdog
+    rust_os::init();
+    rust_os::watchdog::arm(5);
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // the message has the number of ticks in it, so it is formatted and has no `as_str`
+    let mut message: heapless::String<64> = heapless::String::new();
+    let _ = write!(message, "{}", info.message());
+    if message.starts_with(WATCHDOG_FIRED) {
+        serial_println!("[ok]");
+        success_qemu()
+    }
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    fail_qemu()
+}